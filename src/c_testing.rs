@@ -1,227 +1,2140 @@
 use std::{
-    collections::LinkedList,
     fmt::Display,
-    io::{Read, Write},
-    path::PathBuf,
-    process::Command,
+    io::{ErrorKind, PipeWriter, Read, Write},
+    ops::RangeInclusive,
+    os::unix::{
+        fs::PermissionsExt,
+        process::{CommandExt, ExitStatusExt},
+    },
+    path::{Path, PathBuf},
     process::Stdio,
-    time::Duration,
+    process::{Child, Command, ExitStatus},
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
 };
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use tempfile::TempDir;
 use wait_timeout::ChildExt;
 
-use crate::{ProgramResult, TestLog, TestResult, TestingOutcome};
+use crate::cgroup::Cgroup;
+use crate::checker::{self, CheckerFormat, CheckerVerdict};
+use crate::diff::{self, Verdict};
+use crate::interactor;
+use crate::sandbox::{self, Sandbox};
+use crate::termination;
+use crate::{
+    CompilationFailure, IoSizes, JudgeConfig, ProgramResult, Progress, ResultStream, TestLog,
+    TestResult, TestingOutcome, TestingSummary, Visibility,
+};
+
+/// How often memory usage of the running program is sampled.
+const MEMORY_SAMPLING_INTERVAL: Duration = Duration::from_millis(10);
+/// Wait before the first retry of spawning the program.
+const SPAWN_RETRY_BACKOFF: Duration = Duration::from_millis(50);
+/// Time the interactor has to decide after the program exited.
+const INTERACTOR_EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time processes which left the process group of the program have to close
+/// its output after it exited, before the test times out.
+const OUTPUT_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often readers of program's output are checked for being finished.
+const OUTPUT_CLOSE_INTERVAL: Duration = Duration::from_millis(10);
+/// Maximal number of bytes of program's stderr kept for the report.
+const MAX_STDERR_BYTES: u64 = 64 * 1024;
+
+/// Tests are read from this file in the tests directory instead of input
+/// and expected output files when it exists.
+const MANIFEST_NAME: &str = "tests.json";
+/// Extension of gzip compressed test files, e.g. `3.in.gz`.
+const COMPRESSED_EXTENSION: &str = "gz";
+
+/// Replaced with the program directory in arguments of compiled languages.
+const PROGRAM_DIR_PLACEHOLDER: &str = "{program_dir}";
+/// Placeholders of custom commands.
+const SRC_PLACEHOLDER: &str = "{src}";
+const BIN_PLACEHOLDER: &str = "{bin}";
+pub(crate) const ARGS_PLACEHOLDER: &str = "{args}";
+/// Program produced by custom compile command.
+const CUSTOM_PROGRAM: &str = "{program_dir}/compiled_program";
+
+/// Describes how submission written in given language is built and run.
+pub enum Language {
+    /// Sources are compiled by `compiler compiler_args flags output_args sources`,
+    /// then the result is run by `run`, whose first element is the program.
+    /// Sources are all files in the program directory with one of
+    /// `source_extensions`, or just `source_name` if there are none.
+    /// `COMPILE_FLAGS` replace `flags` only. Variables of `compile_env` are
+    /// set for the compiler unless already set in the environment.
+    Compiled {
+        compiler: &'static str,
+        compiler_args: &'static [&'static str],
+        flags: &'static [&'static str],
+        source_name: &'static str,
+        source_extensions: &'static [&'static str],
+        output_args: &'static [&'static str],
+        run: &'static [&'static str],
+        compile_env: &'static [(&'static str, &'static str)],
+    },
+    /// Source is run directly by the interpreter, there is no compilation.
+    Interpreted {
+        interpreter: &'static str,
+        source_name: &'static str,
+    },
+    /// Commands given by the user, split on whitespace. `{src}` is replaced
+    /// with the source, `{bin}` with the program the compilation should
+    /// produce and `{args}` of `run` with arguments of the test. Compilation
+    /// is skipped when there is no `compile` command.
+    Custom {
+        source_name: String,
+        compile: Option<Vec<String>>,
+        run: Vec<String>,
+    },
+}
+
+pub const C_LANGUAGE: Language = Language::Compiled {
+    compiler: "gcc",
+    compiler_args: &[],
+    flags: &["-O2"],
+    source_name: "main.c",
+    source_extensions: &["c"],
+    output_args: &["-o", "{program_dir}/compiled_program"],
+    run: &["{program_dir}/compiled_program"],
+    compile_env: &[],
+};
+
+pub const CPP_LANGUAGE: Language = Language::Compiled {
+    compiler: "g++",
+    compiler_args: &[],
+    flags: &["-O2", "-std=c++17"],
+    source_name: "main.cpp",
+    source_extensions: &["cpp", "cc"],
+    output_args: &["-o", "{program_dir}/compiled_program"],
+    run: &["{program_dir}/compiled_program"],
+    compile_env: &[],
+};
+
+pub const JAVA_LANGUAGE: Language = Language::Compiled {
+    compiler: "javac",
+    compiler_args: &[],
+    flags: &[],
+    source_name: "Main.java",
+    source_extensions: &["java"],
+    output_args: &["-d", "{program_dir}"],
+    run: &["java", "-cp", "{program_dir}", "Main"],
+    compile_env: &[],
+};
+
+/// Only the crate root is passed to rustc, it finds modules on its own.
+pub const RUST_LANGUAGE: Language = Language::Compiled {
+    compiler: "rustc",
+    compiler_args: &[],
+    flags: &["-O", "--edition=2021"],
+    source_name: "main.rs",
+    source_extensions: &[],
+    output_args: &["-o", "{program_dir}/compiled_program"],
+    run: &["{program_dir}/compiled_program"],
+    compile_env: &[],
+};
+
+/// Build runs offline, modules can not be downloaded.
+pub const GO_LANGUAGE: Language = Language::Compiled {
+    compiler: "go",
+    compiler_args: &["build"],
+    flags: &[],
+    source_name: "main.go",
+    source_extensions: &["go"],
+    output_args: &["-o", "{program_dir}/compiled_program"],
+    run: &["{program_dir}/compiled_program"],
+    compile_env: &[("GOPROXY", "off"), ("GOCACHE", "/tmp/alsit_go_cache")],
+};
+
+pub const PYTHON_LANGUAGE: Language = Language::Interpreted {
+    interpreter: "python3",
+    source_name: "main.py",
+};
+
+impl Language {
+    pub fn from_name(name: &str) -> Option<&'static Language> {
+        match name {
+            "C" => Some(&C_LANGUAGE),
+            "CPP" => Some(&CPP_LANGUAGE),
+            "PYTHON" => Some(&PYTHON_LANGUAGE),
+            "JAVA" => Some(&JAVA_LANGUAGE),
+            "GO" => Some(&GO_LANGUAGE),
+            "RUST" => Some(&RUST_LANGUAGE),
+            _ => None,
+        }
+    }
+
+    /// Program building or running the submission, missing when a custom
+    /// command is empty.
+    fn toolchain(&self) -> Option<&str> {
+        match self {
+            Self::Compiled { compiler, .. } => Some(compiler),
+            Self::Interpreted { interpreter, .. } => Some(interpreter),
+            Self::Custom { compile, run, .. } => {
+                compile.as_ref().unwrap_or(run).first().map(String::as_str)
+            }
+        }
+    }
+
+    fn source_name(&self) -> &str {
+        match self {
+            Self::Compiled { source_name, .. } | Self::Interpreted { source_name, .. } => {
+                source_name
+            }
+            Self::Custom { source_name, .. } => source_name,
+        }
+    }
+
+    /// Command running the program, missing when a custom one expands to
+    /// nothing.
+    fn run_command(&self, program_path: &Path, arguments: &[String]) -> Option<Command> {
+        let command = match self {
+            Self::Compiled { run, .. } => {
+                let mut command = Command::new(expand_program_dir(run[0], program_path));
+                command
+                    .args(
+                        run[1..]
+                            .iter()
+                            .map(|arg| expand_program_dir(arg, program_path)),
+                    )
+                    .args(arguments);
+                command
+            }
+            Self::Interpreted {
+                interpreter,
+                source_name,
+            } => {
+                let mut command = Command::new(interpreter);
+                command.arg(program_path.join(source_name)).args(arguments);
+                command
+            }
+            Self::Custom {
+                source_name, run, ..
+            } => {
+                let run = expand_template(
+                    run,
+                    program_path,
+                    &program_path.join(source_name),
+                    arguments,
+                );
+                let (program, arguments) = run.split_first()?;
+                let mut command = Command::new(program);
+                command.args(arguments);
+                command
+            }
+        };
+
+        Some(command)
+    }
+}
+
+/// How the tested program receives its input and produces its output.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    /// Input is written to stdin, output is read from stdout.
+    Stdin,
+    /// Input is copied to `INPUT_FILE_NAME` and output is read from `OUTPUT_FILE_NAME`,
+    /// both inside the working directory the program runs in, a new one for each test.
+    File,
+}
+
+impl FromStr for InputMode {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<InputMode, ()> {
+        match name {
+            "stdin" => Ok(Self::Stdin),
+            "file" => Ok(Self::File),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Ids of tests to run, written as `3,7,10-15`.
+#[derive(Clone)]
+pub struct TestFilter {
+    ranges: Vec<RangeInclusive<u64>>,
+}
+
+impl TestFilter {
+    pub fn matches(&self, id: u64) -> bool {
+        self.ranges.iter().any(|range| range.contains(&id))
+    }
+}
+
+impl FromStr for TestFilter {
+    type Err = ();
+
+    fn from_str(filter: &str) -> Result<TestFilter, ()> {
+        let ranges = filter
+            .split(',')
+            .map(|part| {
+                let part = part.trim();
+                let (start, end) = part.split_once('-').unwrap_or((part, part));
+                let start = start.trim().parse().map_err(|_| ())?;
+                let end = end.trim().parse().map_err(|_| ())?;
+                if start > end {
+                    return Err(());
+                }
+                Ok(start..=end)
+            })
+            .collect::<Result<_, ()>>()?;
+
+        Ok(TestFilter { ranges })
+    }
+}
+
+struct TestCase {
+    id: u64,
+    visibility: Visibility,
+    data: TestData,
+    points: f64,
+}
+
+/// Where input and expected output of a test come from. Expected output is
+/// missing for input-only tests, whose output is judged by the checker or
+/// interactor alone.
+enum TestData {
+    /// `N.in` and `N.out`, with arguments and timeout in files next to them.
+    Files {
+        in_file: PathBuf,
+        out_file: Option<PathBuf>,
+    },
+    /// Test listed in the manifest.
+    Inline {
+        input: String,
+        expected: Option<String>,
+        timeout_millis: Option<u64>,
+    },
+}
+
+impl TestCase {
+    /// Identifies the test in reports about the tests directory.
+    fn describe(&self) -> String {
+        match &self.data {
+            TestData::Files { in_file, .. } => in_file.display().to_string(),
+            TestData::Inline { .. } => format!("{} of {MANIFEST_NAME}", self.id),
+        }
+    }
+}
+
+/// Paths of test's input and expected output for the checker and interactor.
+/// Inline and compressed tests are written to a temporary directory, removed
+/// once dropped.
+struct TestFiles {
+    in_file: PathBuf,
+    out_file: Option<PathBuf>,
+    _dir: Option<TempDir>,
+}
+
+impl TestFiles {
+    fn new(test_case: &TestCase) -> Result<TestFiles, TestError> {
+        let (input, expected) = match &test_case.data {
+            TestData::Files { in_file, out_file }
+                if !is_compressed(in_file) && !out_file.as_deref().is_some_and(is_compressed) =>
+            {
+                return Ok(TestFiles {
+                    in_file: in_file.clone(),
+                    out_file: out_file.clone(),
+                    _dir: None,
+                });
+            }
+            TestData::Files { in_file, out_file } => {
+                let input = read_test_file(in_file)
+                    .map_err(|_| TestError::ReadingInput(in_file.clone()))?;
+                let expected = match out_file {
+                    Some(out_file) => Some(
+                        read_test_file(out_file)
+                            .map_err(|_| TestError::ReadingExpectedOutput(out_file.clone()))?,
+                    ),
+                    None => None,
+                };
+                (input, expected)
+            }
+            TestData::Inline {
+                input, expected, ..
+            } => (
+                input.clone().into_bytes(),
+                expected.clone().map(String::into_bytes),
+            ),
+        };
+
+        let dir = tempfile::Builder::new()
+            .prefix("alsit_testing_")
+            .tempdir()
+            .map_err(|_| TestError::PreparingTestFiles)?;
+        let files = TestFiles {
+            in_file: dir.path().join(format!("{}.in", test_case.id)),
+            out_file: expected
+                .as_ref()
+                .map(|_| dir.path().join(format!("{}.out", test_case.id))),
+            _dir: Some(dir),
+        };
+
+        if std::fs::write(&files.in_file, input).is_err() {
+            return Err(TestError::PreparingTestFiles);
+        }
+        if let (Some(out_file), Some(expected)) = (&files.out_file, &expected) {
+            if std::fs::write(out_file, expected).is_err() {
+                return Err(TestError::PreparingTestFiles);
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+/// Test as listed in the manifest, `timeout` is in milliseconds.
+#[derive(Deserialize)]
+struct ManifestTest {
+    id: u64,
+    input: String,
+    expected: Option<String>,
+    #[serde(default)]
+    sample: bool,
+    points: Option<f64>,
+    timeout: Option<u64>,
+}
+
+enum CompilationResult {
+    /// Anything the compiler reported while succeeding, usually warnings.
+    Successful {
+        warnings: Option<String>,
+        /// Missing for interpreted languages, which are not compiled.
+        time_millis: Option<u64>,
+    },
+    CompilationError(CompilationFailure),
+    /// Compiler or interpreter with the given name is not installed.
+    CompilerNotFound(String),
+}
+
+/// Compiler with the program it should produce and the sources it compiles.
+struct CompileCommand<'a> {
+    command: Command,
+    program: &'a str,
+    sources: Vec<PathBuf>,
+}
+
+enum TestError {
+    ReadingInput(PathBuf),
+    ReadingExpectedOutput(PathBuf),
+    ReadingArguments(PathBuf),
+    ReadingTimeout(PathBuf),
+    PreparingWorkingDir,
+    PreparingInputFile,
+    PreparingTestFiles,
+    ReadingOutputFile,
+    EmptyCommand,
+    Spawning,
+    Sandboxing(String),
+    Checker(String),
+    Interactor(String),
+    WritingStdin,
+    ReadingStdout,
+}
+
+struct ProcessUsage {
+    status: ExitStatus,
+    /// Peak resident set size in megabytes.
+    peak_memory: f64,
+}
+
+enum WaitOutcome {
+    Finished(ProcessUsage),
+    Timeout {
+        exited_after_term: bool,
+    },
+    /// Process was killed after its peak memory (in megabytes) exceeded the limit.
+    MemoryExceeded(f64),
+    OutputLimitExceeded,
+    /// Process was killed as the judge is terminating.
+    Interrupted,
+}
+
+impl Display for TestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReadingInput(path) => {
+                write!(f, "Problem while reading input file {}.", path.display())
+            }
+            Self::ReadingExpectedOutput(path) => write!(
+                f,
+                "Problem while reading expected output file {}.",
+                path.display()
+            ),
+            Self::ReadingArguments(path) => {
+                write!(
+                    f,
+                    "Problem while reading arguments file {}.",
+                    path.display()
+                )
+            }
+            Self::ReadingTimeout(path) => {
+                write!(f, "Problem while reading timeout file {}.", path.display())
+            }
+            Self::PreparingWorkingDir => write!(f, "Problem while preparing working directory."),
+            Self::PreparingInputFile => write!(f, "Problem while preparing input file."),
+            Self::PreparingTestFiles => write!(f, "Problem while writing test to temporary files."),
+            Self::ReadingOutputFile => write!(f, "Problem while reading output file."),
+            Self::EmptyCommand => write!(f, "Command running the program is empty."),
+            Self::Spawning => write!(f, "Problem while starting the program."),
+            Self::Sandboxing(error) => write!(f, "Problem while preparing sandbox: {error}"),
+            Self::Checker(error) | Self::Interactor(error) => write!(f, "{error}"),
+            Self::WritingStdin => write!(f, "Problem while writing to stdin."),
+            Self::ReadingStdout => write!(f, "Problem while reading from stdout."),
+        }
+    }
+}
+
+/// Checks that the submission could be tested, without compiling or running
+/// anything.
+pub fn validate(config: &JudgeConfig) -> ProgramResult {
+    let language = config.language;
+    let mut problems = Vec::new();
+
+    match language.toolchain() {
+        Some(toolchain) if !is_in_path(toolchain) => {
+            problems.push(format!("{toolchain} is not available."));
+        }
+        Some(_) => {}
+        None => problems.push("Command of the language is empty.".into()),
+    }
+
+    let source = config.program_path.join(language.source_name());
+    if !source.is_file() {
+        problems.push(format!("Source file {} is missing.", source.display()));
+    }
+
+    let tests = if config.compile_only {
+        Vec::new()
+    } else {
+        match discover_tests(config, &mut problems) {
+            Ok(tests) => tests,
+            Err(error) => {
+                problems.push(error);
+                Vec::new()
+            }
+        }
+    };
+
+    ProgramResult::ValidationResult {
+        max_score: tests.iter().map(|test| test.points).sum(),
+        tests: tests.iter().map(|test| test.id).collect(),
+        problems,
+    }
+}
+
+pub fn invoke_testing(config: &JudgeConfig) -> ProgramResult {
+    // Judges sharing the program directory, even from different containers,
+    // would overwrite each other's programs. The submission is built and run
+    // in a copy of its own instead, removed once the testing is over.
+    let build_dir = match prepare_build_dir(&config.program_path) {
+        Ok(build_dir) => build_dir,
+        Err(error) => {
+            log::error!("{error}");
+            return ProgramResult::InternalProblem(error);
+        }
+    };
+    let config = &JudgeConfig {
+        program_path: build_dir.path().to_path_buf(),
+        ..config.clone()
+    };
+
+    // Compilation process and json result.
+    let (compiler_warnings, compile_time_millis) = match compile(config) {
+        Ok(CompilationResult::Successful {
+            warnings,
+            time_millis,
+        }) => (warnings, time_millis),
+        Ok(CompilationResult::CompilationError(error)) => {
+            return ProgramResult::CompilationProblem(error);
+        }
+        Ok(CompilationResult::CompilerNotFound(compiler)) => {
+            log::error!("{compiler} is not installed.");
+            return ProgramResult::CompilerNotFound { compiler };
+        }
+        Err(error) => {
+            log::error!("Compilation failed: {error}");
+            return ProgramResult::InternalProblem(error);
+        }
+    };
+
+    // Program runs as another user in the sandbox.
+    if config.sandbox {
+        if let Err(error) = sandbox::expose(&config.program_path) {
+            let error = format!("Unable to expose program directory: {error}");
+            log::error!("{error}");
+            return ProgramResult::InternalProblem(error);
+        }
+    }
+
+    let result = if config.compile_only {
+        log::info!("Compiled only, no tests are run.");
+        Ok(ProgramResult::TestingResult {
+            testing_outcome: TestingOutcome::Success,
+            score: 0.0,
+            max_score: 0.0,
+            compiler_warnings,
+            compile_time_millis,
+            summary: TestingSummary::new(&[], 0.0),
+            tests: Vec::new(),
+        })
+    } else {
+        run_testing(config, compiler_warnings, compile_time_millis)
+    };
+
+    match result {
+        Err(error) => ProgramResult::InternalProblem(error),
+        Ok(result) => result,
+    }
+}
+
+/// Copies the program directory into a new temporary directory with a
+/// random name.
+fn prepare_build_dir(program_path: &Path) -> Result<TempDir, String> {
+    let build_dir = tempfile::Builder::new()
+        .prefix("alsit_testing_build_")
+        .tempdir()
+        .map_err(|error| format!("Unable to create build directory: {error}"))?;
+
+    copy_dir(program_path, build_dir.path()).map_err(|error| {
+        format!(
+            "Unable to copy program directory {}: {error}",
+            program_path.display()
+        )
+    })?;
+
+    Ok(build_dir)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Outputs are raw bytes, only compared as such in binary mode. Otherwise
+/// and in reports, invalid UTF-8 is replaced with U+FFFD.
+fn analyse_result(
+    config: &JudgeConfig,
+    test_case: &TestCase,
+    expected: Vec<u8>,
+    outcome: Vec<u8>,
+    stderr: String,
+    time: u64,
+    memory: f64,
+) -> Result<TestLog, TestError> {
+    if let Some(checker) = &config.checker_path {
+        let files = TestFiles::new(test_case)?;
+        let verdict = match config.checker_format {
+            CheckerFormat::Stdin => {
+                checker::check(checker, &files.in_file, files.out_file.as_deref(), &outcome)
+            }
+            CheckerFormat::Testlib => {
+                // Removed once dropped.
+                let mut output_file = tempfile::Builder::new()
+                    .prefix("alsit_testing_")
+                    .suffix(".output")
+                    .tempfile()
+                    .map_err(|_| TestError::PreparingTestFiles)?;
+                if output_file.write_all(&outcome).is_err() {
+                    return Err(TestError::PreparingTestFiles);
+                }
+                checker::check_testlib(
+                    checker,
+                    &files.in_file,
+                    files.out_file.as_deref(),
+                    output_file.path(),
+                )
+            }
+        };
+
+        return match verdict {
+            Ok(CheckerVerdict::Accepted) => Ok(TestLog::Success { time, memory }),
+            Ok(CheckerVerdict::Rejected(message)) => Ok(wrong_output(
+                config,
+                lossy(expected),
+                lossy(outcome),
+                stderr,
+                Some(message),
+                None,
+            )),
+            Err(error) => Err(TestError::Checker(error)),
+        };
+    }
+
+    let verdict = diff::diff(
+        config.diff_mode,
+        config.float_absolute_epsilon,
+        config.float_relative_epsilon,
+        config.significant_figures,
+        &expected,
+        &outcome,
+    );
+    let (expected, outcome) = (lossy(expected), lossy(outcome));
+
+    Ok(match verdict {
+        Verdict::Accepted => TestLog::Success { time, memory },
+        Verdict::SlightlyWrong(difference) => TestLog::SlightlyWrongOutput {
+            expected_length: expected.chars().count(),
+            got_length: outcome.chars().count(),
+            expected: Some(diff::truncate(&expected, config.max_diff_chars)),
+            got: Some(diff::truncate(&outcome, config.max_diff_chars)),
+            difference,
+        },
+        Verdict::Wrong => {
+            let token_difference = diff::first_token_difference(
+                config.diff_mode,
+                config.float_absolute_epsilon,
+                config.float_relative_epsilon,
+                config.significant_figures,
+                &expected,
+                &outcome,
+            );
+            wrong_output(config, expected, outcome, stderr, None, token_difference)
+        }
+    })
+}
+
+fn lossy(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes)
+        .unwrap_or_else(|error| String::from_utf8_lossy(error.as_bytes()).into_owned())
+}
+
+fn wrong_output(
+    config: &JudgeConfig,
+    expected: String,
+    got: String,
+    stderr: String,
+    checker_message: Option<String>,
+    token_difference: Option<diff::TokenDifference>,
+) -> TestLog {
+    // Forgetting to print the answer at all deserves its own report.
+    if got.trim().is_empty() {
+        return TestLog::NoOutput { stderr };
+    }
+
+    let (first_diff_line, context) =
+        match diff::first_difference(&expected, &got, config.max_diff_chars) {
+            Some((line, context)) => (Some(line), Some(context)),
+            None => (None, None),
+        };
+    let truncate_token =
+        |token: Option<String>| token.map(|token| diff::truncate(&token, config.max_diff_chars));
+
+    TestLog::WrongOutput {
+        expected_length: Some(expected.chars().count()),
+        got_length: Some(got.chars().count()),
+        expected: Some(diff::truncate(&expected, config.max_diff_chars)),
+        got: Some(diff::truncate(&got, config.max_diff_chars)),
+        first_diff_line,
+        context,
+        first_diff_token_index: token_difference.as_ref().map(|difference| difference.index),
+        expected_token: truncate_token(
+            token_difference
+                .as_ref()
+                .and_then(|difference| difference.expected.clone()),
+        ),
+        got_token: truncate_token(token_difference.and_then(|difference| difference.got)),
+        stderr,
+        checker_message,
+        hint: diff::hint(&expected, &got),
+    }
+}
+
+/// Sizes of input and output are recorded in `sizes` as soon as they are
+/// known, wall time of the program in `wall_time_millis` once it finished,
+/// whatever its outcome.
+fn test(
+    config: &JudgeConfig,
+    test_case: &TestCase,
+    sizes: &mut IoSizes,
+    wall_time_millis: &mut Option<u64>,
+) -> Result<TestLog, TestError> {
+    let (mut in_content, out_content, arguments, timeout_millis) = match &test_case.data {
+        TestData::Files { in_file, out_file } => {
+            let in_content = match read_test_file(in_file) {
+                Ok(content) => content,
+                Err(_) => {
+                    return Err(TestError::ReadingInput(in_file.clone()));
+                }
+            };
+            let out_content = match out_file {
+                Some(out_file) => match read_test_file(out_file) {
+                    Ok(content) => content,
+                    Err(_) => {
+                        return Err(TestError::ReadingExpectedOutput(out_file.to_path_buf()));
+                    }
+                },
+                None => Vec::new(),
+            };
+
+            (
+                in_content,
+                out_content,
+                read_arguments(in_file)?,
+                read_timeout(in_file, config.timeout_millis)?,
+            )
+        }
+        TestData::Inline {
+            input,
+            expected,
+            timeout_millis,
+        } => (
+            input.clone().into_bytes(),
+            expected.clone().map(String::into_bytes).unwrap_or_default(),
+            Vec::new(),
+            timeout_millis.unwrap_or(config.timeout_millis),
+        ),
+    };
+    if config.append_input_newline && in_content.last().is_some_and(|&last| last != b'\n') {
+        in_content.push(b'\n');
+    }
+    sizes.input_bytes = Some(in_content.len() as u64);
+
+    // Every test runs in a new directory, so that files written by the
+    // program can not affect other tests. It is removed once dropped.
+    let working_dir = tempfile::Builder::new()
+        .prefix("alsit_testing_")
+        .tempdir()
+        .map_err(|_| TestError::PreparingWorkingDir)?;
+    let working_dir = working_dir.path();
+    // Paths relative to the judge would not work from the working directory.
+    let program_path =
+        std::path::absolute(&config.program_path).map_err(|_| TestError::Spawning)?;
+
+    let mut command = config
+        .language
+        .run_command(&program_path, &arguments)
+        .ok_or(TestError::EmptyCommand)?;
+    command.process_group(0).current_dir(working_dir);
+
+    // Environment of the judge may hold secrets. `PATH` is kept, as
+    // interpreters and custom commands are looked up in it.
+    command.env_clear();
+    if let Some(path) = std::env::var_os("PATH") {
+        command.env("PATH", path);
+    }
+    command.envs(config.program_env.iter().cloned());
+
+    let cgroup = Cgroup::create(
+        &format!("alsit_testing_{}_test_{}", std::process::id(), test_case.id),
+        config.memory_limit_mib,
+        config.process_limit,
+    );
+    if let Some(cgroup) = &cgroup {
+        cgroup.add(&mut command);
+    }
+    let process_limit_reached = || cgroup.as_ref().is_some_and(Cgroup::process_limit_reached);
+
+    if let Some(limit) = config
+        .process_limit
+        .filter(|_| !cgroup.as_ref().is_some_and(Cgroup::limits_processes))
+    {
+        // Limit is set after fork, so it only applies to the program.
+        unsafe {
+            command.pre_exec(move || set_process_limit(limit));
+        }
+    }
+
+    let sandbox = if config.sandbox {
+        // Expected outputs of this and earlier tests are not for the program.
+        let hidden_dirs = [&config.tests_path, &config.output.dir]
+            .into_iter()
+            .filter_map(|dir| std::path::absolute(dir).ok())
+            .filter(|dir| !working_dir.starts_with(dir) && !program_path.starts_with(dir))
+            .collect::<Vec<_>>();
+        Some(
+            sandbox::restrict(&mut command, working_dir, &hidden_dirs)
+                .map_err(TestError::Sandboxing)?,
+        )
+    } else {
+        None
+    };
+
+    if let Some(interactor) = &config.interactor_path {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let process = spawn_with_retries(&mut command, config.spawn_retries)
+            .map_err(|error| spawn_error(error, sandbox.as_ref()))?;
+        return test_interactive(
+            config,
+            test_case,
+            process,
+            interactor,
+            timeout_millis,
+            cgroup.as_ref(),
+            wall_time_millis,
+        );
+    }
+
+    let stdin_content = match config.input_mode {
+        InputMode::Stdin => in_content,
+        InputMode::File => {
+            let input_path = working_dir.join(&config.input_file_name);
+            if std::fs::write(input_path, &in_content).is_err() {
+                return Err(TestError::PreparingInputFile);
+            }
+            Vec::new()
+        }
+    };
+
+    // Judge keeps a read end of program's stdin of its own, to find out how
+    // much of the input was left unread once the program finished.
+    let (mut stdin_reader, stdin) = std::io::pipe().map_err(|_| TestError::Spawning)?;
+    let program_stdin = stdin_reader.try_clone().map_err(|_| TestError::Spawning)?;
+
+    command
+        .stdin(program_stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut process_spawn = match spawn_with_retries(&mut command, config.spawn_retries) {
+        Ok(process) => process,
+        Err(error) => {
+            return Err(spawn_error(error, sandbox.as_ref()));
+        }
+    };
+
+    // Stderr is drained on the side, so that the program never blocks on a full pipe.
+    let stderr_reader = spawn_stderr_reader(&mut process_spawn);
+    let output_exceeded = Arc::new(AtomicBool::new(false));
+    let stdout_reader = spawn_stdout_reader(
+        &mut process_spawn,
+        config.max_output_bytes,
+        output_exceeded.clone(),
+    );
+
+    let start_time = Instant::now();
+
+    // Input is written on the side, because the program may not read all of
+    // it before it exits or gets killed. Stdout is drained at the same time,
+    // so programs printing prompts between reads never block on either pipe.
+    let stdin_writer = spawn_stdin_writer(stdin, stdin_content);
+    drop(command);
+
+    let wait_outcome = wait_with_usage(
+        &process_spawn,
+        Duration::from_millis(timeout_millis),
+        sampled_memory_limit(config, cgroup.as_ref()),
+        &output_exceeded,
+        Duration::from_millis(config.timeout_grace_millis),
+    );
+    let time = start_time.elapsed().as_millis() as u64;
+    *wall_time_millis = Some(time);
+
+    // Whatever is left in the pipe was not read by the program. Draining it
+    // also lets the writer finish.
+    let unread = std::io::copy(&mut stdin_reader, &mut std::io::sink());
+    if config.input_mode == InputMode::Stdin {
+        sizes.input_consumed = unread.ok().map(|unread| unread == 0);
+    }
+
+    let output_deadline = close_output(cgroup.as_ref());
+    let output_held = || TestLog::Timeout {
+        time_limit_millis: timeout_millis,
+        time: start_time.elapsed().as_millis() as u64,
+        exited_after_term: false,
+    };
+
+    match wait_outcome {
+        WaitOutcome::Finished(mut usage) => {
+            if let Some(memory_used) = account_cgroup(cgroup.as_ref(), &mut usage) {
+                return Ok(TestLog::MemoryExceeded { memory_used });
+            }
+
+            let Some(stderr) = join_reader(stderr_reader, output_deadline) else {
+                return Ok(output_held());
+            };
+            let stderr = stderr.unwrap_or_default();
+
+            // Writes never fail for input the program left unread, the judge
+            // holds a read end of the pipe until the writer finishes.
+            if !matches!(stdin_writer.join(), Ok(Ok(()))) {
+                return Err(TestError::WritingStdin);
+            }
+
+            if let Some(code) = usage.status.code() {
+                // Output of a program that failed is not judged, whatever it
+                // printed before. This includes syntax errors and uncaught
+                // exceptions of interpreted languages.
+                if code != 0 {
+                    return Ok(TestLog::RuntimeError {
+                        exit_code: Some(code),
+                        signal: None,
+                        signal_name: None,
+                        process_limit_reached: process_limit_reached(),
+                        stderr,
+                    });
+                }
+
+                let Some(read) = join_reader(stdout_reader, output_deadline) else {
+                    return Ok(output_held());
+                };
+                let read = read.unwrap_or_else(|_| Err(ErrorKind::Other.into()));
+
+                let read = match config.input_mode {
+                    InputMode::Stdin => read,
+                    InputMode::File => {
+                        let output_path = working_dir.join(&config.output_file_name);
+                        match std::fs::metadata(&output_path) {
+                            Ok(metadata) if metadata.len() > config.max_output_bytes => {
+                                return Ok(TestLog::OutputLimitExceeded {
+                                    limit_bytes: config.max_output_bytes,
+                                });
+                            }
+                            Ok(_) => {}
+                            Err(error) if error.kind() == ErrorKind::NotFound => {
+                                return Ok(TestLog::NoOutputFile {
+                                    file_name: config.output_file_name.clone(),
+                                    stderr,
+                                });
+                            }
+                            Err(_) => {
+                                return Err(TestError::ReadingOutputFile);
+                            }
+                        }
+
+                        match std::fs::read(output_path) {
+                            Ok(content) => Ok(content),
+                            Err(_) => {
+                                return Err(TestError::ReadingOutputFile);
+                            }
+                        }
+                    }
+                };
+
+                if let Ok(output) = &read {
+                    sizes.output_bytes = Some(output.len() as u64);
+                }
+
+                match read {
+                    Err(_) => Err(TestError::ReadingStdout),
+                    Ok(output) => analyse_result(
+                        config,
+                        test_case,
+                        out_content,
+                        output,
+                        stderr,
+                        time,
+                        usage.peak_memory,
+                    ),
+                }
+            } else {
+                let signal = usage.status.signal();
+
+                Ok(TestLog::RuntimeError {
+                    exit_code: None,
+                    signal,
+                    signal_name: signal.map(signal_name),
+                    process_limit_reached: process_limit_reached(),
+                    stderr,
+                })
+            }
+        }
+        WaitOutcome::Timeout { exited_after_term } => Ok(TestLog::Timeout {
+            time_limit_millis: timeout_millis,
+            time,
+            exited_after_term,
+        }),
+        WaitOutcome::MemoryExceeded(memory_used) => Ok(TestLog::MemoryExceeded { memory_used }),
+        WaitOutcome::OutputLimitExceeded => Ok(TestLog::OutputLimitExceeded {
+            limit_bytes: config.max_output_bytes,
+        }),
+        WaitOutcome::Interrupted => Ok(TestLog::Skipped),
+    }
+}
+
+/// Connects the started program to the interactor, which decides about the
+/// result. Input and output modes do not apply, the program always talks to
+/// the interactor through stdin and stdout. Timeout covers the whole session,
+/// as the program waits for the interactor while it thinks.
+fn test_interactive(
+    config: &JudgeConfig,
+    test_case: &TestCase,
+    mut process: Child,
+    interactor: &Path,
+    timeout_millis: u64,
+    cgroup: Option<&Cgroup>,
+    wall_time_millis: &mut Option<u64>,
+) -> Result<TestLog, TestError> {
+    let stderr_reader = spawn_stderr_reader(&mut process);
+    let start_time = Instant::now();
+
+    let files = match TestFiles::new(test_case) {
+        Ok(files) => files,
+        Err(error) => {
+            let _ = process.kill();
+            let _ = process.wait();
+            return Err(error);
+        }
+    };
+    let interaction = match interactor::spawn(
+        interactor,
+        &files.in_file,
+        files.out_file.as_deref(),
+        &mut process,
+    ) {
+        Ok(interaction) => interaction,
+        Err(error) => {
+            let _ = process.kill();
+            let _ = process.wait();
+            return Err(TestError::Interactor(error));
+        }
+    };
+
+    // Output goes to the interactor, so there is no output to limit.
+    let wait_outcome = wait_with_usage(
+        &process,
+        Duration::from_millis(timeout_millis),
+        sampled_memory_limit(config, cgroup),
+        &AtomicBool::new(false),
+        Duration::from_millis(config.timeout_grace_millis),
+    );
+    let time = start_time.elapsed().as_millis() as u64;
+    *wall_time_millis = Some(time);
+
+    let mut usage = match wait_outcome {
+        WaitOutcome::Finished(usage) => usage,
+        WaitOutcome::Timeout { exited_after_term } => {
+            interactor::stop(interaction);
+            return Ok(TestLog::Timeout {
+                time_limit_millis: timeout_millis,
+                time,
+                exited_after_term,
+            });
+        }
+        WaitOutcome::MemoryExceeded(memory_used) => {
+            interactor::stop(interaction);
+            return Ok(TestLog::MemoryExceeded { memory_used });
+        }
+        WaitOutcome::OutputLimitExceeded => {
+            interactor::stop(interaction);
+            return Ok(TestLog::OutputLimitExceeded {
+                limit_bytes: config.max_output_bytes,
+            });
+        }
+        WaitOutcome::Interrupted => {
+            interactor::stop(interaction);
+            return Ok(TestLog::Skipped);
+        }
+    };
+
+    if let Some(memory_used) = account_cgroup(cgroup, &mut usage) {
+        interactor::stop(interaction);
+        return Ok(TestLog::MemoryExceeded { memory_used });
+    }
+
+    let Some(stderr) = join_reader(stderr_reader, close_output(cgroup)) else {
+        interactor::stop(interaction);
+        return Ok(TestLog::Timeout {
+            time_limit_millis: timeout_millis,
+            time: start_time.elapsed().as_millis() as u64,
+            exited_after_term: false,
+        });
+    };
+    let stderr = stderr.unwrap_or_default();
+
+    match (usage.status.code(), usage.status.signal()) {
+        (Some(code), _) if code != 0 => {
+            interactor::stop(interaction);
+            return Ok(TestLog::RuntimeError {
+                exit_code: Some(code),
+                signal: None,
+                signal_name: None,
+                process_limit_reached: cgroup.is_some_and(Cgroup::process_limit_reached),
+                stderr,
+            });
+        }
+        // Program writing after the interactor already rejected it is killed
+        // by SIGPIPE, the rejection explains more.
+        (None, Some(signal)) if signal != libc::SIGPIPE => {
+            interactor::stop(interaction);
+            return Ok(TestLog::RuntimeError {
+                exit_code: None,
+                signal: Some(signal),
+                signal_name: Some(signal_name(signal)),
+                process_limit_reached: cgroup.is_some_and(Cgroup::process_limit_reached),
+                stderr,
+            });
+        }
+        _ => {}
+    }
+
+    match interactor::verdict(interaction, INTERACTOR_EXIT_TIMEOUT) {
+        Ok(CheckerVerdict::Accepted) => Ok(TestLog::Success {
+            time,
+            memory: usage.peak_memory,
+        }),
+        Ok(CheckerVerdict::Rejected(message)) => Ok(TestLog::WrongOutput {
+            expected: None,
+            got: None,
+            expected_length: None,
+            got_length: None,
+            first_diff_line: None,
+            context: None,
+            first_diff_token_index: None,
+            expected_token: None,
+            got_token: None,
+            stderr,
+            checker_message: Some(message),
+            hint: None,
+        }),
+        Err(error) => Err(TestError::Interactor(error)),
+    }
+}
+
+/// Reads whitespace separated arguments of the program from `N.args` file
+/// next to `N.in`. No arguments are passed when the file doesn't exist.
+fn read_arguments(in_file: &Path) -> Result<Vec<String>, TestError> {
+    let args_file = uncompressed_path(in_file).with_extension("args");
+
+    match std::fs::read_to_string(&args_file) {
+        Ok(content) => Ok(content.split_whitespace().map(String::from).collect()),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(_) => Err(TestError::ReadingArguments(args_file)),
+    }
+}
+
+/// Reads timeout in milliseconds of the test from `N.timeout` file next to `N.in`.
+/// `default_millis` is used when the file doesn't exist.
+fn read_timeout(in_file: &Path, default_millis: u64) -> Result<u64, TestError> {
+    let timeout_file = uncompressed_path(in_file).with_extension("timeout");
+
+    match std::fs::read_to_string(&timeout_file) {
+        Ok(content) => match content.trim().parse::<u64>() {
+            Ok(timeout) => Ok(timeout),
+            Err(_) => Err(TestError::ReadingTimeout(timeout_file)),
+        },
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(default_millis),
+        Err(_) => Err(TestError::ReadingTimeout(timeout_file)),
+    }
+}
+
+/// Compiler may succeed without producing the program, which would then fail
+/// to start in every test, or leave a program older than the sources, which
+/// would run old code. Programs outside the program directory, like `java`,
+/// are not checked.
+fn check_compiled_program(
+    program: &str,
+    program_path: &Path,
+    sources: &[PathBuf],
+) -> Result<(), String> {
+    if !program.contains(PROGRAM_DIR_PLACEHOLDER) {
+        return Ok(());
+    }
+
+    let path = expand_program_dir(program, program_path);
+    let metadata = match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 => {
+            metadata
+        }
+        Ok(_) => return Err(format!("Compiled program {path} is not executable.")),
+        Err(_) => return Err(format!("Compiled program {path} is missing.")),
+    };
+
+    let newest_source = sources
+        .iter()
+        .filter_map(|source| {
+            std::fs::metadata(source)
+                .and_then(|meta| meta.modified())
+                .ok()
+        })
+        .max();
+    match (metadata.modified(), newest_source) {
+        (Ok(built), Some(newest_source)) if built < newest_source => Err(format!(
+            "Compiled program {path} is older than its sources."
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Toolchains do not agree on how to print their version, so it is only
+/// checked that the program can be found.
+fn is_in_path(program: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+
+    std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+}
+
+/// Files in the program directory with one of given extensions, sorted so
+/// that the compiler is always invoked the same way.
+fn find_sources(program_path: &Path, extensions: &[&str]) -> Result<Vec<PathBuf>, String> {
+    let files = match std::fs::read_dir(program_path) {
+        Ok(files) => files,
+        Err(_) => {
+            return Err("Error while scanning program directory.".into());
+        }
+    };
+
+    let mut sources = Vec::new();
+    for file in files {
+        let path = match file {
+            Ok(file) => file.path(),
+            Err(_) => {
+                return Err("Error while scanning program directory.".into());
+            }
+        };
+
+        let is_source = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| extensions.contains(&ext));
+        if is_source && path.is_file() {
+            sources.push(path);
+        }
+    }
+
+    sources.sort();
+
+    Ok(sources)
+}
+
+fn expand_program_dir(arg: &str, program_path: &Path) -> String {
+    arg.replace(PROGRAM_DIR_PLACEHOLDER, &program_path.to_string_lossy())
+}
+
+/// Fills placeholders of a custom command, `{args}` has to stand alone as
+/// it expands to any number of arguments.
+fn expand_template(
+    template: &[String],
+    program_path: &Path,
+    source: &Path,
+    arguments: &[String],
+) -> Vec<String> {
+    let mut expanded = Vec::new();
+
+    for arg in template {
+        if arg == ARGS_PLACEHOLDER {
+            expanded.extend(arguments.iter().cloned());
+            continue;
+        }
+
+        let arg = arg
+            .replace(BIN_PLACEHOLDER, CUSTOM_PROGRAM)
+            .replace(SRC_PLACEHOLDER, &source.to_string_lossy());
+        expanded.push(expand_program_dir(&arg, program_path));
+    }
+
+    expanded
+}
+
+fn set_address_space_limit(limit_bytes: u64) -> std::io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: limit_bytes as libc::rlim_t,
+        rlim_max: limit_bytes as libc::rlim_t,
+    };
+
+    if unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+fn set_process_limit(limit: u64) -> std::io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: limit as libc::rlim_t,
+        rlim_max: limit as libc::rlim_t,
+    };
+
+    if unsafe { libc::setrlimit(libc::RLIMIT_NPROC, &limit) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Human readable description of a signal, e.g. "Segmentation fault".
+fn signal_name(signal: i32) -> String {
+    let description = unsafe { libc::strsignal(signal) };
+
+    if description.is_null() {
+        format!("Signal {signal}")
+    } else {
+        unsafe { std::ffi::CStr::from_ptr(description) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Reads at most `MAX_STDERR_BYTES` of program's stderr, discarding the rest.
+fn spawn_stderr_reader(process: &mut Child) -> std::thread::JoinHandle<String> {
+    let stderr = process.stderr.take();
+
+    std::thread::spawn(move || {
+        let mut stderr = match stderr {
+            Some(stderr) => stderr,
+            None => return String::new(),
+        };
+
+        let mut content = Vec::new();
+        let _ = (&mut stderr)
+            .take(MAX_STDERR_BYTES)
+            .read_to_end(&mut content);
+        let _ = std::io::copy(&mut stderr, &mut std::io::sink());
+
+        String::from_utf8_lossy(&content).into_owned()
+    })
+}
+
+/// Kills processes of the program which left its process group, when they
+/// can be found in its cgroup. Returns until when the others may keep the
+/// output of the program open.
+fn close_output(cgroup: Option<&Cgroup>) -> Instant {
+    if let Some(cgroup) = cgroup {
+        cgroup.kill();
+    }
+    Instant::now() + OUTPUT_CLOSE_TIMEOUT
+}
+
+/// Waits for the reader of program's output until the deadline. Returns
+/// nothing when the output is still open then.
+fn join_reader<T>(
+    reader: std::thread::JoinHandle<T>,
+    deadline: Instant,
+) -> Option<std::thread::Result<T>> {
+    while !reader.is_finished() {
+        if Instant::now() >= deadline {
+            return None;
+        }
+        std::thread::sleep(OUTPUT_CLOSE_INTERVAL);
+    }
 
-enum CompilationResult {
-    Successful,
-    CompilationError(String),
+    Some(reader.join())
 }
 
-enum TestError {
-    WritingStdin,
-    SignalKill,
-    ReadingStdout,
-}
+/// Retries spawning when the system is temporarily out of processes or
+/// memory, waiting twice as long before each retry. Other failures, like a
+/// missing binary, are returned right away.
+fn spawn_with_retries(command: &mut Command, retries: u32) -> std::io::Result<Child> {
+    let mut backoff = SPAWN_RETRY_BACKOFF;
+    let mut attempt = 0;
 
-impl Display for TestError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let text = match self {
-            Self::WritingStdin => "Problem while writing to stdin.",
-            Self::SignalKill => "Program killed by signal.",
-            Self::ReadingStdout => "Problem while reading from stdout.",
-        };
+    loop {
+        match command.spawn() {
+            Err(error)
+                if attempt < retries
+                    && matches!(error.raw_os_error(), Some(libc::EAGAIN | libc::ENOMEM)) =>
+            {
+                log::warn!("Retrying to start the program: {error}");
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            spawned => return spawned,
+        }
+    }
+}
 
-        write!(f, "{text}")
+/// Failures of the sandbox are problems of the judge's host rather than of
+/// the program, they are reported with their reason.
+fn spawn_error(error: std::io::Error, sandbox: Option<&Sandbox>) -> TestError {
+    if sandbox.is_some_and(Sandbox::failed) {
+        TestError::Sandboxing(error.to_string())
+    } else {
+        TestError::Spawning
     }
 }
 
-pub fn invoke_testing() -> ProgramResult {
-    // Compilation process and json result.
-    match compile() {
-        Ok(CompilationResult::Successful) => {}
-        Ok(CompilationResult::CompilationError(error)) => {
-            return ProgramResult::CompilationProblem(error);
-        }
-        Err(error) => {
-            println!("ERROR COMPILATION = {}", error);
-            return ProgramResult::InternalProblem(error);
+/// Writes the whole input to program's stdin and closes it, so that programs
+/// reading until the end of input finish right away.
+fn spawn_stdin_writer(
+    mut stdin: PipeWriter,
+    content: Vec<u8>,
+) -> std::thread::JoinHandle<std::io::Result<()>> {
+    std::thread::spawn(move || {
+        stdin.write_all(&content)?;
+        stdin.flush()?;
+        drop(stdin);
+        Ok(())
+    })
+}
+
+/// Reads at most `limit` bytes of program's stdout. When the program writes
+/// more, the flag is raised so that the program gets killed.
+fn spawn_stdout_reader(
+    process: &mut Child,
+    limit: u64,
+    output_exceeded: Arc<AtomicBool>,
+) -> std::thread::JoinHandle<std::io::Result<Vec<u8>>> {
+    let stdout = process.stdout.take();
+
+    std::thread::spawn(move || {
+        let mut stdout = match stdout {
+            Some(stdout) => stdout,
+            None => return Err(ErrorKind::BrokenPipe.into()),
+        };
+
+        let mut content = Vec::new();
+        (&mut stdout).take(limit + 1).read_to_end(&mut content)?;
+
+        if content.len() as u64 > limit {
+            output_exceeded.store(true, Ordering::SeqCst);
+            let _ = std::io::copy(&mut stdout, &mut std::io::sink());
+            content.truncate(limit as usize);
         }
+
+        Ok(content)
+    })
+}
+
+/// Memory limit enforced by sampling, unless the cgroup enforces it.
+fn sampled_memory_limit(config: &JudgeConfig, cgroup: Option<&Cgroup>) -> Option<u64> {
+    match cgroup {
+        Some(_) => None,
+        None => config.memory_limit_mib,
     }
+}
 
-    match run_testing() {
-        Err(error) => ProgramResult::InternalProblem(error),
-        Ok((list, outcome)) => ProgramResult::TestingResult {
-            testing_outcome: outcome,
-            tests: list,
-        },
+/// Replaces sampled peak memory with the one accounted by the cgroup.
+/// Returns the peak when the program was killed for exceeding the limit.
+fn account_cgroup(cgroup: Option<&Cgroup>, usage: &mut ProcessUsage) -> Option<f64> {
+    let cgroup = cgroup?;
+
+    if let Some(peak_memory) = cgroup.peak_memory_mib() {
+        usage.peak_memory = peak_memory;
     }
+
+    cgroup.oom_killed().then_some(usage.peak_memory)
+}
+
+/// Reads peak resident set size (in kB) of running process from procfs.
+fn sample_peak_memory(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+
+    status
+        .lines()
+        .find(|line| line.starts_with("VmHWM:"))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
 }
 
-fn analyse_result(expected: String, outcome: String, time: u64, memory: f64) -> TestLog {
-    if expected == outcome {
-        return TestLog::Success { time, memory };
+/// Program runs in its own process group, killing the group also kills
+/// processes it started.
+fn kill_group(pid: u32) {
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
     }
+}
 
-    if expected.trim() == outcome {
-        return TestLog::SlightlyWrongOutput {
-            expected,
-            got: outcome,
-        };
+fn kill_and_reap(process: &Child, receiver: &mpsc::Receiver<(i32, u64)>) {
+    kill_group(process.id());
+    // Waiting thread reaps the killed process.
+    let _ = receiver.recv();
+}
+
+/// Asks the process group to stop with SIGTERM and kills it after `grace`.
+/// Returns whether the program exited by itself in the meantime, rather
+/// than being killed by the signal.
+fn terminate_and_reap(
+    process: &Child,
+    receiver: &mpsc::Receiver<(i32, u64)>,
+    grace: Duration,
+) -> bool {
+    if grace.is_zero() {
+        kill_and_reap(process, receiver);
+        return false;
     }
 
-    TestLog::WrongOutput {
-        expected,
-        got: outcome,
-    }
-}
-
-// TODO: ERROR PROOF
-fn test(in_file: &PathBuf, out_file: &PathBuf) -> Result<TestLog, TestError> {
-    let in_content = std::fs::read_to_string(in_file).unwrap();
-    let out_content = std::fs::read_to_string(out_file).unwrap();
-
-    let mut process_spawn = Command::new(format!(
-        "{}{}",
-        crate::PROGRAM_PATH,
-        crate::COMPILED_PROGRAM_NAME
-    ))
-    .stdin(Stdio::piped())
-    .stdout(Stdio::piped())
-    .spawn()
-    .unwrap();
-
-    match process_spawn
-        .stdin
-        .as_mut()
-        .unwrap()
-        .write_all(in_content.as_bytes())
-    {
-        Ok(()) => {}
+    unsafe {
+        libc::kill(-(process.id() as libc::pid_t), libc::SIGTERM);
+    }
+    match receiver.recv_timeout(grace) {
+        Ok((status, _)) => {
+            kill_group(process.id());
+            ExitStatus::from_raw(status).code().is_some()
+        }
         Err(_) => {
-            return Err(TestError::WritingStdin);
+            kill_and_reap(process, receiver);
+            false
         }
-    };
+    }
+}
 
-    match process_spawn
-        .wait_timeout(Duration::from_millis(*crate::TESTING_TIMEOUT_TIME_MILLS))
-        .unwrap()
-    {
-        Some(status) => {
-            if status.code().is_some() {
-                let mut output = String::new();
+/// Waits for the process to finish while sampling its memory usage.
+/// Process group is killed if it runs out of time, exceeds memory limit (in MiB)
+/// or writes too much output. On timeout it gets `grace` to exit after SIGTERM.
+fn wait_with_usage(
+    process: &Child,
+    timeout: Duration,
+    memory_limit_mib: Option<u64>,
+    output_exceeded: &AtomicBool,
+    grace: Duration,
+) -> WaitOutcome {
+    let pid = process.id();
+    let (sender, receiver) = mpsc::channel();
 
-                match process_spawn.stdout.unwrap().read_to_string(&mut output) {
-                    Err(_) => Err(TestError::ReadingStdout),
-                    Ok(_) => Ok(analyse_result(out_content, output, 0, 0.0)),
+    std::thread::spawn(move || {
+        let mut status = 0;
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+        let result = unsafe { libc::wait4(pid as libc::pid_t, &mut status, 0, &mut usage) };
+        if result == pid as libc::pid_t {
+            let _ = sender.send((status, usage.ru_maxrss as u64));
+        }
+    });
+
+    let start_time = Instant::now();
+    let memory_limit_kb = memory_limit_mib.map(|limit| limit * 1024);
+    let mut peak_memory_kb = 0;
+
+    loop {
+        match receiver.recv_timeout(MEMORY_SAMPLING_INTERVAL) {
+            Ok((status, rusage_peak_kb)) => {
+                // Processes left behind would keep running into the next test
+                // and could hold program's stdout open.
+                kill_group(pid);
+
+                // Short-lived programs might finish before the first sample.
+                peak_memory_kb = peak_memory_kb.max(rusage_peak_kb);
+                let peak_memory = peak_memory_kb as f64 / 1024.0;
+
+                if memory_limit_kb.is_some_and(|limit| peak_memory_kb > limit) {
+                    return WaitOutcome::MemoryExceeded(peak_memory);
                 }
-            } else {
-                Err(TestError::SignalKill)
+
+                if output_exceeded.load(Ordering::SeqCst) {
+                    return WaitOutcome::OutputLimitExceeded;
+                }
+
+                return WaitOutcome::Finished(ProcessUsage {
+                    status: ExitStatus::from_raw(status),
+                    peak_memory,
+                });
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if let Some(sample) = sample_peak_memory(pid) {
+                    peak_memory_kb = peak_memory_kb.max(sample);
+                }
+
+                if memory_limit_kb.is_some_and(|limit| peak_memory_kb > limit) {
+                    kill_and_reap(process, &receiver);
+                    return WaitOutcome::MemoryExceeded(peak_memory_kb as f64 / 1024.0);
+                }
+
+                if output_exceeded.load(Ordering::SeqCst) {
+                    kill_and_reap(process, &receiver);
+                    return WaitOutcome::OutputLimitExceeded;
+                }
+
+                if start_time.elapsed() >= timeout {
+                    return WaitOutcome::Timeout {
+                        exited_after_term: terminate_and_reap(process, &receiver, grace),
+                    };
+                }
+
+                if termination::requested() {
+                    kill_and_reap(process, &receiver);
+                    return WaitOutcome::Interrupted;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return WaitOutcome::Timeout {
+                    exited_after_term: false,
+                };
             }
-        }
-        None => {
-            let _ = process_spawn.kill();
-            Ok(TestLog::Timeout {
-                time_limit_millis: *crate::TESTING_TIMEOUT_TIME_MILLS,
-            })
         }
     }
 }
 
-fn get_id(path: &std::path::Path) -> u64 {
-    let mut path_c = path.to_path_buf();
-    let _ = path_c.set_extension("");
+fn is_compressed(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq(COMPRESSED_EXTENSION))
+}
 
-    path_c
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .parse()
-        .unwrap()
+/// Path without the compression extension, e.g. `3.in` for `3.in.gz`.
+fn uncompressed_path(path: &Path) -> PathBuf {
+    if is_compressed(path) {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Reads input or expected output of a test, decompressing it if needed.
+fn read_test_file(path: &Path) -> std::io::Result<Vec<u8>> {
+    if !is_compressed(path) {
+        return std::fs::read(path);
+    }
+
+    let mut content = Vec::new();
+    GzDecoder::new(std::fs::File::open(path)?).read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Test id is the numeric stem of its input file, e.g. `12` for `12.in` or
+/// `sample_12.in.gz`, the prefix marks sample tests.
+fn get_id(path: &Path) -> Option<(u64, Visibility)> {
+    let path = uncompressed_path(path);
+    let stem = path.file_stem()?.to_str()?;
+
+    match stem.strip_prefix("sample_") {
+        Some(id) => Some((id.parse().ok()?, Visibility::Sample)),
+        None => Some((stem.parse().ok()?, Visibility::Hidden)),
+    }
+}
+
+/// Finds all tests in the tests directory sorted by their ids, from the
+/// manifest if there is one. Reasons for skipping tests are added to `skipped`.
+fn discover_tests(
+    config: &JudgeConfig,
+    skipped: &mut Vec<String>,
+) -> Result<Vec<TestCase>, String> {
+    let manifest = config.tests_path.join(MANIFEST_NAME);
+    let mut tests = if manifest.is_file() {
+        read_manifest(config, &manifest)?
+    } else {
+        scan_tests(config, skipped)?
+    };
+
+    tests.sort_by_key(|test| test.id);
+
+    // Sample and hidden test can not share an id, e.g. `3.in` and `sample_3.in`.
+    if let Some(pair) = tests.windows(2).find(|pair| pair[0].id == pair[1].id) {
+        return Err(format!(
+            "Tests {} and {} have the same id.",
+            pair[0].describe(),
+            pair[1].describe()
+        ));
+    }
+
+    // Missing tests are usually a mistake in packaging them.
+    for pair in tests.windows(2).filter(|pair| pair[1].id != pair[0].id + 1) {
+        log::warn!(
+            "Tests between {} and {} are missing.",
+            pair[0].describe(),
+            pair[1].describe()
+        );
+    }
+
+    if let Some(filter) = &config.test_filter {
+        tests.retain(|test| filter.matches(test.id));
+    }
+
+    Ok(tests)
+}
+
+/// Expected output may only be missing when something else judges the output.
+fn judged_without_expected(config: &JudgeConfig) -> bool {
+    config.checker_path.is_some() || config.interactor_path.is_some()
+}
+
+/// Reads tests listed as `[{id, input, expected, sample, points, timeout}]`,
+/// only `id` and `input` are required.
+fn read_manifest(config: &JudgeConfig, manifest: &Path) -> Result<Vec<TestCase>, String> {
+    let content = match std::fs::read_to_string(manifest) {
+        Ok(content) => content,
+        Err(_) => {
+            return Err(format!("Problem while reading {}.", manifest.display()));
+        }
+    };
+    let listed: Vec<ManifestTest> = match serde_json::from_str(&content) {
+        Ok(listed) => listed,
+        Err(error) => {
+            return Err(format!(
+                "Problem while parsing {}: {error}",
+                manifest.display()
+            ));
+        }
+    };
+
+    let mut tests = Vec::new();
+    for test in listed {
+        // Input-only test without a checker is a mistake in the tests,
+        // which must not be blamed on the submission.
+        if test.expected.is_none() && !judged_without_expected(config) {
+            return Err(format!(
+                "Tests are misconfigured: test {} of {MANIFEST_NAME} has no expected output and no checker or interactor is set.",
+                test.id
+            ));
+        }
+
+        let points = test.points.unwrap_or(1.0);
+        if points < 0.0 {
+            return Err(format!(
+                "Test {} of {MANIFEST_NAME} has negative points.",
+                test.id
+            ));
+        }
+
+        tests.push(TestCase {
+            id: test.id,
+            visibility: if test.sample {
+                Visibility::Sample
+            } else {
+                Visibility::Hidden
+            },
+            data: TestData::Inline {
+                input: test.input,
+                expected: test.expected,
+                timeout_millis: test.timeout,
+            },
+            points,
+        });
+    }
+
+    Ok(tests)
 }
 
-fn run_testing() -> Result<(LinkedList<TestResult>, TestingOutcome), String> {
-    let files = match std::fs::read_dir(crate::TEST_PATH) {
+fn scan_tests(config: &JudgeConfig, skipped: &mut Vec<String>) -> Result<Vec<TestCase>, String> {
+    let files = match std::fs::read_dir(&config.tests_path) {
         Ok(res) => res,
         Err(_) => {
             return Err("Error while scanning directory.".into());
         }
     };
 
-    let mut in_files = Vec::new();
+    let mut tests = Vec::new();
     for file in files {
-        let path = file.unwrap().path();
-
-        match path.extension() {
-            None => {}
-            Some(ext) => {
-                if ext.eq("in") {
-                    in_files.push(path);
-                }
+        let path = match file {
+            Ok(file) => file.path(),
+            Err(_) => {
+                return Err("Error while scanning directory.".into());
             }
+        };
+
+        let uncompressed = uncompressed_path(&path);
+        match uncompressed.extension() {
+            Some(ext) if ext.eq(config.input_extension.as_str()) => {}
+            _ => continue,
         }
-    }
 
-    in_files.sort_by(|x, y| {
-        let x_name: u64 = get_id(x);
+        // Uncompressed variant is preferred when both exist.
+        if is_compressed(&path) && uncompressed.exists() {
+            continue;
+        }
 
-        let y_name: u64 = get_id(y);
+        let (id, visibility) = match get_id(&path) {
+            Some(id) => id,
+            None => {
+                skipped.push(format!(
+                    "Skipping {}, its name is not a test id.",
+                    path.display()
+                ));
+                continue;
+            }
+        };
 
-        x_name.cmp(&y_name)
-    });
+        let out_file = uncompressed.with_extension(&config.output_extension);
+        let compressed_out_file = uncompressed.with_extension(format!(
+            "{}.{COMPRESSED_EXTENSION}",
+            config.output_extension
+        ));
+        let out_file = if out_file.exists() {
+            Some(out_file)
+        } else if compressed_out_file.exists() {
+            Some(compressed_out_file)
+        } else if judged_without_expected(config) {
+            None
+        } else {
+            return Err(format!(
+                "Tests are misconfigured: {} has no expected output and no checker or interactor is set.",
+                path.display()
+            ));
+        };
 
-    let mut list: LinkedList<TestResult> = LinkedList::new();
+        let points = read_points(&path)?;
+        tests.push(TestCase {
+            id,
+            visibility,
+            data: TestData::Files {
+                in_file: path,
+                out_file,
+            },
+            points,
+        });
+    }
 
-    for file in in_files {
-        let in_path = file;
+    Ok(tests)
+}
 
-        let mut out_path = in_path.clone();
-        let _ = out_path.set_extension("out");
+fn run_testing(
+    config: &JudgeConfig,
+    compiler_warnings: Option<String>,
+    compile_time_millis: Option<u64>,
+) -> Result<ProgramResult, String> {
+    let mut skipped = Vec::new();
+    let tests = discover_tests(config, &mut skipped)?;
+    for reason in skipped {
+        log::warn!("{reason}");
+    }
+    log::info!("Discovered {} tests.", tests.len());
+    let max_score = tests.iter().map(|test| test.points).sum();
 
-        let test_id: u64 = get_id(&in_path);
+    let jobs = config.parallel_jobs.get();
 
-        match test(&in_path, &out_path) {
+    let deadline = config
+        .global_timeout_millis
+        .map(|millis| Instant::now() + Duration::from_millis(millis));
+    let stream = match &config.output.ndjson_path {
+        Some(path) => match ResultStream::create(path) {
+            Ok(stream) => Some(Mutex::new(stream)),
             Err(error) => {
-                list.push_back(TestResult::new(
-                    test_id,
-                    TestLog::InternalError(error.to_string()),
-                ));
-                return Ok((list, TestingOutcome::InternalError));
-            }
-            Ok(TestLog::Success { time, memory }) => {
-                list.push_back(TestResult::new(test_id, TestLog::Success { time, memory }));
-            }
-            Ok(result) => {
-                list.push_back(TestResult::new(test_id, result.clone()));
-                return Ok((list, result.outcome()));
+                return Err(format!("Unable to create {}: {error}", path.display()));
             }
+        },
+        None => None,
+    };
+    let next_test = AtomicUsize::new(0);
+    let failure_found = AtomicBool::new(false);
+    let results = Mutex::new(Vec::new());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                if config.stop_on_first_failure && failure_found.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                // Tests are picked in order of their ids, so all tests before
+                // a failed one are always run.
+                let test_case = match tests.get(next_test.fetch_add(1, Ordering::SeqCst)) {
+                    Some(test_case) => test_case,
+                    None => break,
+                };
+
+                let start_time = Instant::now();
+                let mut sizes = IoSizes::default();
+                let mut wall_time_millis = None;
+                let result = if deadline.is_some_and(|deadline| start_time >= deadline)
+                    || termination::requested()
+                {
+                    TestLog::Skipped
+                } else {
+                    match test(config, test_case, &mut sizes, &mut wall_time_millis) {
+                        Err(error) => {
+                            log::error!("Test {} failed internally: {error}", test_case.id);
+                            TestLog::InternalError(error.to_string())
+                        }
+                        Ok(result) => result,
+                    }
+                };
+                log::info!(
+                    "Test {} finished with {:?} in {} ms.",
+                    test_case.id,
+                    result.outcome(),
+                    start_time.elapsed().as_millis()
+                );
+
+                if is_failure(config, result.outcome()) {
+                    failure_found.store(true, Ordering::SeqCst);
+                }
+
+                let result = TestResult::new(
+                    test_case.id,
+                    test_case.visibility,
+                    result,
+                    test_case.points,
+                    sizes,
+                    wall_time_millis,
+                    config,
+                );
+                if let Some(stream) = &stream {
+                    if let Err(error) = stream.lock().unwrap().write(&result) {
+                        log::warn!("Unable to stream result: {error}");
+                    }
+                }
+
+                let mut results = results.lock().unwrap();
+                results.push(result);
+
+                let progress = Progress {
+                    tests_total: tests.len(),
+                    tests: &results,
+                };
+                if let Err(error) = crate::write_progress(&config.output, &progress) {
+                    log::warn!("Unable to write progress: {error}");
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|result| result.test_id);
+
+    // Other workers could have run tests after the first failure.
+    if config.stop_on_first_failure {
+        if let Some(failed) = results
+            .iter()
+            .position(|result| is_failure(config, result.test_result.outcome()))
+        {
+            results.truncate(failed + 1);
         }
     }
 
-    Ok((list, TestingOutcome::Success))
+    let testing_outcome = TestingOutcome::aggregate(results.iter().map(|result| {
+        config
+            .slightly_wrong_policy
+            .apply(result.test_result.outcome())
+    }));
+    let summary = TestingSummary::new(&results, max_score);
+
+    Ok(ProgramResult::TestingResult {
+        testing_outcome,
+        score: summary.score,
+        max_score,
+        compiler_warnings,
+        compile_time_millis,
+        summary,
+        tests: results,
+    })
+}
+
+/// Failed test stops the testing when stopping on the first failure. Skipped
+/// tests never do, so that all of them are reported.
+fn is_failure(config: &JudgeConfig, outcome: TestingOutcome) -> bool {
+    !matches!(
+        config.slightly_wrong_policy.apply(outcome),
+        TestingOutcome::Success | TestingOutcome::Skipped
+    )
+}
+
+/// Reads points available for the test from `N.points` file next to `N.in`.
+/// Test is worth one point when the file doesn't exist.
+fn read_points(in_file: &Path) -> Result<f64, String> {
+    let points_file = uncompressed_path(in_file).with_extension("points");
+
+    match std::fs::read_to_string(&points_file) {
+        Ok(content) => match content.trim().parse::<f64>() {
+            Ok(points) if points >= 0.0 => Ok(points),
+            _ => Err(format!(
+                "Problem while reading points file {}.",
+                points_file.display()
+            )),
+        },
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(1.0),
+        Err(_) => Err(format!(
+            "Problem while reading points file {}.",
+            points_file.display()
+        )),
+    }
 }
 
-fn compile() -> Result<CompilationResult, String> {
-    let process_output = Command::new("gcc")
-        .arg("-O2")
-        .arg(format!("{}{}", crate::PROGRAM_PATH, crate::PROGRAM_NAME))
-        .arg("-o")
-        .arg(format!(
-            "{}{}",
-            crate::PROGRAM_PATH,
-            crate::COMPILED_PROGRAM_NAME
-        ))
-        .output();
+fn compile(config: &JudgeConfig) -> Result<CompilationResult, String> {
+    let Some(CompileCommand {
+        mut command,
+        program,
+        sources,
+    }) = compile_command(config)?
+    else {
+        // Missing interpreter would otherwise fail every test on its own.
+        if let Language::Interpreted { interpreter, .. } = config.language {
+            if !is_in_path(interpreter) {
+                return Ok(CompilationResult::CompilerNotFound(interpreter.to_string()));
+            }
+        }
+
+        return Ok(CompilationResult::Successful {
+            warnings: None,
+            time_millis: None,
+        });
+    };
+
+    command
+        // Build tools like go look for project files in the current directory.
+        .current_dir(&config.program_path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        // Compiler driver runs the actual compilation in its subprocesses,
+        // which have to be killed with it.
+        .process_group(0);
 
-    let output = match process_output {
-        Ok(o) => o,
+    if let Some(limit_mib) = config.compile_memory_limit_mib {
+        let limit_bytes = limit_mib * 1024 * 1024;
+        // Limit is set after fork, so it only applies to the compiler.
+        unsafe {
+            command.pre_exec(move || set_address_space_limit(limit_bytes));
+        }
+    }
+
+    // Program left by a previous compilation must not pass the check below.
+    if program.contains(PROGRAM_DIR_PLACEHOLDER) {
+        let _ = std::fs::remove_file(expand_program_dir(program, &config.program_path));
+    }
+
+    log::info!("Compiling with {command:?}.");
+    let argv: Vec<String> = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+
+    let start_time = Instant::now();
+    let mut process = match command.spawn() {
+        Ok(process) => process,
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            return Ok(CompilationResult::CompilerNotFound(argv[0].clone()));
+        }
         Err(_) => {
             return Err("Internal error occured while starting compilation process.".into());
         }
     };
 
+    // Compiler may report more than fits into the pipe before it finishes.
+    let stderr = process.stderr.take();
+    let stderr_reader = std::thread::spawn(move || {
+        let mut content = Vec::new();
+        if let Some(mut stderr) = stderr {
+            let _ = stderr.read_to_end(&mut content);
+        }
+        content
+    });
+
+    let status = match process.wait_timeout(Duration::from_millis(config.compile_timeout_millis)) {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            unsafe {
+                libc::kill(-(process.id() as libc::pid_t), libc::SIGKILL);
+            }
+            let _ = process.wait();
+            return Ok(CompilationResult::CompilationError(CompilationFailure {
+                message: "compilation timed out".into(),
+                command: argv,
+                exit_code: None,
+                signal: Some(libc::SIGKILL),
+                compile_time_millis: start_time.elapsed().as_millis() as u64,
+            }));
+        }
+        Err(_) => {
+            return Err("Internal error occured while waiting for compilation process.".into());
+        }
+    };
+
+    let compile_time_millis = start_time.elapsed().as_millis() as u64;
+    let output = std::process::Output {
+        status,
+        stdout: Vec::new(),
+        stderr: stderr_reader.join().unwrap_or_default(),
+    };
+
     if let Some(code) = output.status.code() {
         if code == 0 {
-            Ok(CompilationResult::Successful)
+            check_compiled_program(program, &config.program_path, &sources)?;
+            let warnings = String::from_utf8_lossy(&output.stderr);
+            Ok(CompilationResult::Successful {
+                warnings: (!warnings.trim().is_empty()).then(|| warnings.into_owned()),
+                time_millis: Some(compile_time_millis),
+            })
         } else {
             let comunicate = match String::from_utf8(output.stderr) {
                 Ok(result) => result,
@@ -232,9 +2145,290 @@ fn compile() -> Result<CompilationResult, String> {
                 }
             };
 
-            Ok(CompilationResult::CompilationError(comunicate))
+            Ok(CompilationResult::CompilationError(CompilationFailure {
+                message: comunicate,
+                command: argv,
+                exit_code: Some(code),
+                signal: None,
+                compile_time_millis,
+            }))
         }
     } else {
-        Err("Compilation process terminated by sginal.".into())
+        // Compiler killed by a signal, e.g. after running out of memory.
+        Ok(CompilationResult::CompilationError(CompilationFailure {
+            message: String::from_utf8_lossy(&output.stderr).into_owned(),
+            command: argv,
+            exit_code: None,
+            signal: output.status.signal(),
+            compile_time_millis,
+        }))
+    }
+}
+
+/// Compiler command of the language, `None` for languages without compilation.
+fn compile_command(config: &JudgeConfig) -> Result<Option<CompileCommand<'_>>, String> {
+    match config.language {
+        Language::Compiled {
+            compiler,
+            compiler_args,
+            flags,
+            source_name,
+            source_extensions,
+            output_args,
+            run,
+            compile_env,
+        } => {
+            let mut command = Command::new(compiler);
+            command.args(*compiler_args);
+            match &config.compile_flags {
+                Some(custom_flags) => command.args(custom_flags),
+                None => command.args(*flags),
+            };
+
+            for (name, value) in *compile_env {
+                if std::env::var_os(name).is_none() {
+                    command.env(name, value);
+                }
+            }
+
+            let mut sources = find_sources(&config.program_path, source_extensions)?;
+            if sources.is_empty() {
+                // Compiler reports the missing main source itself.
+                sources.push(config.program_path.join(source_name));
+            }
+
+            command
+                .args(
+                    output_args
+                        .iter()
+                        .map(|arg| expand_program_dir(arg, &config.program_path)),
+                )
+                .args(&sources);
+
+            Ok(Some(CompileCommand {
+                command,
+                program: run[0],
+                sources,
+            }))
+        }
+        Language::Custom {
+            source_name,
+            compile: Some(compile),
+            run,
+        } => {
+            let source = config.program_path.join(source_name);
+            let compile = expand_template(compile, &config.program_path, &source, &[]);
+            let (compiler, arguments) = compile.split_first().ok_or("Compile command is empty.")?;
+            let mut command = Command::new(compiler);
+            command.args(arguments);
+
+            let program = match run.first() {
+                Some(program) if !run.iter().any(|arg| arg.contains(BIN_PLACEHOLDER)) => program,
+                _ => CUSTOM_PROGRAM,
+            };
+
+            Ok(Some(CompileCommand {
+                command,
+                program,
+                sources: vec![source],
+            }))
+        }
+        Language::Custom { compile: None, .. } | Language::Interpreted { .. } => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Runs `script` by `sh` on a single test, returns the log of the test.
+    /// `{tests}` in the script is replaced with the tests directory.
+    fn run_script(
+        script: &str,
+        input: &[u8],
+        expected: &[u8],
+        configure: impl FnOnce(&mut JudgeConfig),
+    ) -> TestLog {
+        let tests = tempfile::tempdir().unwrap();
+        let program = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        std::fs::write(tests.path().join("1.in"), input).unwrap();
+        std::fs::write(tests.path().join("1.out"), expected).unwrap();
+        let script = script.replace("{tests}", &tests.path().to_string_lossy());
+        std::fs::write(program.path().join("main.sh"), script).unwrap();
+
+        let language = Box::leak(Box::new(Language::Custom {
+            source_name: "main.sh".into(),
+            compile: None,
+            run: vec!["sh".into(), "{src}".into()],
+        }));
+        let mut config = JudgeConfig::new(language, 10_000);
+        config.tests_path = tests.path().into();
+        config.program_path = program.path().into();
+        config.output.dir = output.path().into();
+        configure(&mut config);
+
+        match invoke_testing(&config) {
+            ProgramResult::TestingResult { mut tests, .. } => tests.remove(0).test_result,
+            _ => panic!("Testing did not finish."),
+        }
+    }
+
+    #[test]
+    fn custom_command_expanding_to_nothing_is_missing() {
+        let language = Language::Custom {
+            source_name: "main.sh".into(),
+            compile: None,
+            run: vec![ARGS_PLACEHOLDER.into()],
+        };
+        assert!(language.run_command(Path::new("/program"), &[]).is_none());
+
+        let language = Language::Custom {
+            source_name: "main.sh".into(),
+            compile: None,
+            run: Vec::new(),
+        };
+        assert!(language.toolchain().is_none());
+    }
+
+    #[test]
+    fn testlib_checker_reads_output_file() {
+        let checker_dir = tempfile::tempdir().unwrap();
+        let checker = checker_dir.path().join("checker.sh");
+        std::fs::write(&checker, "#!/bin/sh\ncmp -s \"$2\" \"$3\"\n").unwrap();
+        std::fs::set_permissions(&checker, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let log = run_script("echo 42\n", b"", b"42\n", |config| {
+            config.checker_path = Some(checker);
+            config.checker_format = CheckerFormat::Testlib;
+        });
+
+        assert!(matches!(log, TestLog::Success { .. }));
+    }
+
+    #[test]
+    fn inline_test_files_are_removed_once_dropped() {
+        let test_case = TestCase {
+            id: 1,
+            visibility: Visibility::Hidden,
+            data: TestData::Inline {
+                input: "1 2\n".into(),
+                expected: Some("3\n".into()),
+                timeout_millis: None,
+            },
+            points: 1.0,
+        };
+
+        let files = TestFiles::new(&test_case).ok().unwrap();
+        let in_file = files.in_file.clone();
+        assert_eq!(std::fs::read(&in_file).unwrap(), b"1 2\n");
+        assert_eq!(
+            std::fs::read(files.out_file.as_ref().unwrap()).unwrap(),
+            b"3\n"
+        );
+
+        drop(files);
+        assert!(!in_file.exists());
+    }
+
+    #[test]
+    fn program_echoing_large_input_does_not_deadlock() {
+        // Far more than pipe buffers hold, so input and output must flow together.
+        let input = "0123456789abcdef\n".repeat(512 * 1024);
+
+        let log = run_script("cat\n", input.as_bytes(), input.as_bytes(), |_| {});
+
+        assert!(matches!(log, TestLog::Success { .. }));
+    }
+
+    #[test]
+    fn program_prompting_before_each_read_does_not_deadlock() {
+        let script = "while echo enter:; read -r line; do echo \"$line\"; done\n";
+        let lines = 200_000;
+        let input: String = (0..lines).map(|line| format!("{line}\n")).collect();
+        let expected: String = (0..lines)
+            .map(|line| format!("enter:\n{line}\n"))
+            .chain(["enter:\n".to_string()])
+            .collect();
+
+        let log = run_script(script, input.as_bytes(), expected.as_bytes(), |_| {});
+
+        assert!(matches!(log, TestLog::Success { .. }));
+    }
+
+    #[test]
+    fn program_leaving_input_unread_is_judged() {
+        let input = "1\n".repeat(512 * 1024);
+
+        let log = run_script("echo 42\n", input.as_bytes(), b"42\n", |_| {});
+
+        assert!(matches!(log, TestLog::Success { .. }));
+    }
+
+    #[test]
+    fn output_held_by_escaped_process_times_out() {
+        let start_time = Instant::now();
+
+        // Program waits for the process to leave its group before exiting.
+        let script = "echo 1\nsetsid sleep 10 &\nsleep 0.2\n";
+        let log = run_script(script, b"", b"1\n", |config| config.timeout_millis = 1000);
+
+        assert!(matches!(log, TestLog::Timeout { .. }));
+        assert!(start_time.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    #[ignore = "sandbox requires root"]
+    fn sandboxed_program_runs_subprocesses() {
+        let log = run_script("cat | cat\n", b"1 2\n", b"1 2\n", |config| {
+            config.sandbox = true
+        });
+
+        assert!(matches!(log, TestLog::Success { .. }));
+    }
+
+    #[test]
+    #[ignore = "sandbox requires root"]
+    fn sandboxed_program_writes_output_file() {
+        let log = run_script("cat in.txt > out.txt\n", b"42\n", b"42\n", |config| {
+            config.sandbox = true;
+            config.input_mode = InputMode::File;
+            config.input_file_name = "in.txt".into();
+            config.output_file_name = "out.txt".into();
+        });
+
+        assert!(matches!(log, TestLog::Success { .. }));
+    }
+
+    #[test]
+    #[ignore = "sandbox requires root"]
+    fn sandboxed_program_can_not_signal_the_judge() {
+        let log = run_script(
+            "kill -KILL $PPID\necho alive\n",
+            b"",
+            b"alive\n",
+            |config| config.sandbox = true,
+        );
+
+        assert!(matches!(log, TestLog::Success { .. }));
+    }
+
+    #[test]
+    #[ignore = "sandbox requires root"]
+    fn sandboxed_program_can_not_read_tests() {
+        let script = "test -e {tests}/1.out && echo visible || echo hidden\n";
+
+        let log = run_script(script, b"", b"hidden\n", |config| config.sandbox = true);
+
+        assert!(matches!(log, TestLog::Success { .. }));
+    }
+
+    #[test]
+    #[ignore = "sandbox requires root"]
+    fn sandboxed_program_runs_unprivileged() {
+        let log = run_script("id -u\n", b"", b"65534\n", |config| config.sandbox = true);
+
+        assert!(matches!(log, TestLog::Success { .. }));
     }
 }
@@ -0,0 +1,245 @@
+//! Memory accounting of the tested program in a cgroup v2 of its own, created
+//! under the cgroup of the judge. It is only used when the memory controller
+//! is delegated to the judge's cgroup, otherwise memory usage is sampled from
+//! procfs. Number of its processes is limited as well when
+//! the pids controller is delegated too.
+
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Written to `cgroup.procs`, moves the writing process.
+const CURRENT_PROCESS: &[u8] = b"0";
+/// Child of the judge's cgroup the judge moves itself into.
+const JUDGE_LEAF: &str = "judge";
+/// Killed processes may take a moment to leave the cgroup before its removal.
+const REMOVAL_ATTEMPTS: u32 = 10;
+const REMOVAL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct Cgroup {
+    path: PathBuf,
+    procs: File,
+    /// Whether the process limit is enforced by the pids controller.
+    limits_processes: bool,
+}
+
+impl Cgroup {
+    /// Creates cgroup of the given name limited to `memory_limit_mib` and
+    /// `process_limit` processes, the latter only if the pids controller is
+    /// available. Returns `None` when cgroups v2 with the memory controller
+    /// can not be used.
+    pub fn create(
+        name: &str,
+        memory_limit_mib: Option<u64>,
+        process_limit: Option<u64>,
+    ) -> Option<Cgroup> {
+        let path = tests_parent()?.join(name);
+        std::fs::create_dir(&path).ok()?;
+
+        let procs = match File::options().write(true).open(path.join("cgroup.procs")) {
+            Ok(procs) => procs,
+            Err(_) => {
+                let _ = std::fs::remove_dir(&path);
+                return None;
+            }
+        };
+        let mut cgroup = Cgroup {
+            path,
+            procs,
+            limits_processes: false,
+        };
+
+        if let Some(limit_mib) = memory_limit_mib {
+            let limit_bytes = (limit_mib * 1024 * 1024).to_string();
+            std::fs::write(cgroup.path.join("memory.max"), limit_bytes).ok()?;
+            // Swapping out would let the program use more than the limit.
+            let _ = std::fs::write(cgroup.path.join("memory.swap.max"), "0");
+        }
+        if let Some(limit) = process_limit {
+            cgroup.limits_processes =
+                std::fs::write(cgroup.path.join("pids.max"), limit.to_string()).is_ok();
+        }
+
+        Some(cgroup)
+    }
+
+    /// Makes the command join the cgroup between fork and exec, before
+    /// anything else could allocate memory outside of it.
+    pub fn add(&self, command: &mut Command) {
+        let procs = self.procs.as_raw_fd();
+
+        unsafe {
+            command.pre_exec(move || {
+                let written = libc::write(
+                    procs,
+                    CURRENT_PROCESS.as_ptr() as *const libc::c_void,
+                    CURRENT_PROCESS.len(),
+                );
+                if written < 0 {
+                    return Err(Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Peak memory usage of all processes in the cgroup in MiB.
+    pub fn peak_memory_mib(&self) -> Option<f64> {
+        let peak: u64 = std::fs::read_to_string(self.path.join("memory.peak"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+
+        Some(peak as f64 / 1024.0 / 1024.0)
+    }
+
+    /// Whether the kernel killed a process of the cgroup for exceeding the limit.
+    pub fn oom_killed(&self) -> bool {
+        let Ok(events) = std::fs::read_to_string(self.path.join("memory.events")) else {
+            return false;
+        };
+
+        events
+            .lines()
+            .filter_map(|line| line.strip_prefix("oom_kill "))
+            .any(|count| count.trim().parse::<u64>().is_ok_and(|count| count > 0))
+    }
+
+    /// Kills all processes of the cgroup, including those which left the
+    /// process group of the program.
+    pub fn kill(&self) {
+        let _ = std::fs::write(self.path.join("cgroup.kill"), "1");
+    }
+
+    pub fn limits_processes(&self) -> bool {
+        self.limits_processes
+    }
+
+    /// Whether a process of the cgroup failed to fork for exceeding the
+    /// process limit.
+    pub fn process_limit_reached(&self) -> bool {
+        let Ok(events) = std::fs::read_to_string(self.path.join("pids.events")) else {
+            return false;
+        };
+
+        events
+            .lines()
+            .filter_map(|line| line.strip_prefix("max "))
+            .any(|count| count.trim().parse::<u64>().is_ok_and(|count| count > 0))
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        self.kill();
+
+        for _ in 0..REMOVAL_ATTEMPTS {
+            if std::fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            std::thread::sleep(REMOVAL_INTERVAL);
+        }
+        log::warn!("Unable to remove cgroup {}.", self.path.display());
+    }
+}
+
+/// Whether cgroups of tests can limit the number of their processes.
+pub fn processes_limitable() -> bool {
+    tests_parent().is_some_and(|path| controller_enabled(&path, "pids"))
+}
+
+/// Parent of the cgroups of tests, if they get the memory controller.
+/// Prepared once, later tests reuse it.
+fn tests_parent() -> Option<PathBuf> {
+    static TESTS_PARENT: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+    TESTS_PARENT
+        .get_or_init(|| match prepare_tests_parent() {
+            Ok(path) => Some(path),
+            Err(reason) => {
+                log::warn!(
+                    "Memory usage is sampled from procfs, cgroups can not be used: {reason}"
+                );
+                None
+            }
+        })
+        .clone()
+}
+
+/// Controllers can only be enabled for children of a cgroup without
+/// processes of its own, which the cgroup of the judge under systemd or in a
+/// container never is. The judge moves itself into a leaf child first, and
+/// cgroups of tests are created next to that leaf.
+fn prepare_tests_parent() -> Result<PathBuf, String> {
+    let membership = std::fs::read_to_string("/proc/self/cgroup")
+        .map_err(|error| format!("unable to read /proc/self/cgroup: {error}"))?;
+    // Only the unified hierarchy has an empty controller list.
+    let relative = membership
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or("the judge is not in a cgroup v2")?;
+    let mount = unified_mount().ok_or("cgroup2 is not mounted")?;
+    let path = mount.join(relative.trim_start_matches('/'));
+
+    let available = std::fs::read_to_string(path.join("cgroup.controllers"))
+        .map_err(|error| format!("unable to read controllers of {}: {error}", path.display()))?;
+    let available: Vec<&str> = available.split_whitespace().collect();
+    if !available.contains(&"memory") {
+        return Err(format!(
+            "memory controller is not delegated to {}",
+            path.display()
+        ));
+    }
+
+    if !controller_enabled(&path, "memory") {
+        let leaf = path.join(JUDGE_LEAF);
+        match std::fs::create_dir(&leaf) {
+            Ok(()) => {}
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {}
+            Err(error) => return Err(format!("unable to create {}: {error}", leaf.display())),
+        }
+        std::fs::write(leaf.join("cgroup.procs"), CURRENT_PROCESS)
+            .map_err(|error| format!("unable to move the judge to {}: {error}", leaf.display()))?;
+        std::fs::write(path.join("cgroup.subtree_control"), "+memory").map_err(|error| {
+            format!(
+                "unable to enable memory controller in {}: {error}",
+                path.display()
+            )
+        })?;
+    }
+
+    // Process limits are optional, tests can be run without them.
+    if available.contains(&"pids") && !controller_enabled(&path, "pids") {
+        if let Err(error) = std::fs::write(path.join("cgroup.subtree_control"), "+pids") {
+            log::warn!(
+                "Unable to enable pids controller in {}: {error}",
+                path.display()
+            );
+        }
+    }
+
+    Ok(path)
+}
+
+/// Whether children of the cgroup get the controller.
+fn controller_enabled(path: &Path, controller: &str) -> bool {
+    std::fs::read_to_string(path.join("cgroup.subtree_control"))
+        .is_ok_and(|enabled| enabled.split_whitespace().any(|name| name == controller))
+}
+
+/// Mount point of the unified hierarchy, `/sys/fs/cgroup` unless the host
+/// mixes it with cgroups v1.
+fn unified_mount() -> Option<PathBuf> {
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+
+    mounts.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        (fields.get(2) == Some(&"cgroup2")).then(|| PathBuf::from(fields[1]))
+    })
+}
@@ -0,0 +1,168 @@
+use std::{
+    io::{Read, Write},
+    os::unix::process::CommandExt,
+    path::Path,
+    process::{Command, Output, Stdio},
+    str::FromStr,
+    time::Duration,
+};
+
+use wait_timeout::ChildExt;
+
+/// Time the checker has to judge a single output.
+const CHECKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Exit codes of testlib checkers, presentation error is judged as wrong output.
+const TESTLIB_ACCEPTED: i32 = 0;
+const TESTLIB_WRONG_ANSWER: i32 = 1;
+const TESTLIB_PRESENTATION_ERROR: i32 = 2;
+const TESTLIB_FAIL: i32 = 3;
+
+pub enum CheckerVerdict {
+    Accepted,
+    Rejected(String),
+}
+
+/// How the checker gets program's output and reports its verdict.
+#[derive(Clone, Copy)]
+pub enum CheckerFormat {
+    /// Output on stdin, see `check`.
+    Stdin,
+    /// Output in a file, see `check_testlib`.
+    Testlib,
+}
+
+impl FromStr for CheckerFormat {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<CheckerFormat, ()> {
+        match name {
+            "stdin" => Ok(Self::Stdin),
+            "testlib" => Ok(Self::Testlib),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Runs checker as `checker <input file> [expected output file]` with program's
+/// output on its stdin. Expected output is not passed for input-only tests.
+/// Exit code 0 accepts the output, any other rejects it and checker's stdout
+/// explains why.
+pub fn check(
+    checker: &Path,
+    in_file: &Path,
+    out_file: Option<&Path>,
+    output: &[u8],
+) -> Result<CheckerVerdict, String> {
+    let mut command = Command::new(checker);
+    command.arg(in_file).args(out_file);
+    let checker_output = run(command, Some(output.to_owned()))?;
+
+    match checker_output.status.code() {
+        Some(0) => Ok(CheckerVerdict::Accepted),
+        Some(_) => Ok(CheckerVerdict::Rejected(
+            String::from_utf8_lossy(&checker_output.stdout).into_owned(),
+        )),
+        None => Err("Checker terminated by signal.".into()),
+    }
+}
+
+/// Runs checker as `checker <input file> <output file> <answer file>`, like
+/// testlib checkers expect. Input-only tests get an empty answer file. Exit
+/// code 0 accepts the output, 1 and 2 reject it and 3 means the checker itself
+/// failed. Checker's comment is taken from its stderr and stdout.
+pub fn check_testlib(
+    checker: &Path,
+    in_file: &Path,
+    out_file: Option<&Path>,
+    output_file: &Path,
+) -> Result<CheckerVerdict, String> {
+    let mut command = Command::new(checker);
+    command
+        .arg(in_file)
+        .arg(output_file)
+        .arg(out_file.unwrap_or(Path::new("/dev/null")));
+    let checker_output = run(command, None)?;
+
+    let comment = [&checker_output.stderr, &checker_output.stdout]
+        .iter()
+        .map(|comment| String::from_utf8_lossy(comment).trim().to_owned())
+        .filter(|comment| !comment.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    match checker_output.status.code() {
+        Some(TESTLIB_ACCEPTED) => Ok(CheckerVerdict::Accepted),
+        Some(TESTLIB_WRONG_ANSWER | TESTLIB_PRESENTATION_ERROR) => {
+            Ok(CheckerVerdict::Rejected(comment))
+        }
+        Some(TESTLIB_FAIL) => Err(format!("Checker failed: {comment}")),
+        Some(code) => Err(format!("Checker exited with unexpected code {code}.")),
+        None => Err("Checker terminated by signal.".into()),
+    }
+}
+
+/// Runs the checker with `input` on its stdin, or with no stdin at all.
+/// Checker running longer than `CHECKER_TIMEOUT` is killed together with
+/// processes it started, it would block the worker forever otherwise.
+fn run(mut command: Command, input: Option<Vec<u8>>) -> Result<Output, String> {
+    let mut process = match command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+    {
+        Ok(process) => process,
+        Err(_) => {
+            return Err("Problem while starting the checker.".into());
+        }
+    };
+
+    // Checker may produce output before reading all of its input.
+    let stdin = process.stdin.take();
+    let writer = std::thread::spawn(move || {
+        if let (Some(mut stdin), Some(input)) = (stdin, input) {
+            let _ = stdin.write_all(&input);
+        }
+    });
+    let read = |pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut content = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut content);
+            }
+            content
+        })
+    };
+    let stdout_reader = read(process.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let stderr_reader = read(process.stderr.take().map(|pipe| Box::new(pipe) as _));
+
+    let status = process.wait_timeout(CHECKER_TIMEOUT);
+    // Processes left behind could hold the pipes open.
+    unsafe {
+        libc::kill(-(process.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let status = match status {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            let _ = process.wait();
+            return Err("Checker timed out.".into());
+        }
+        Err(_) => {
+            let _ = process.wait();
+            return Err("Problem while waiting for the checker.".into());
+        }
+    };
+
+    let _ = writer.join();
+    Ok(Output {
+        status,
+        stdout: stdout_reader.join().unwrap_or_default(),
+        stderr: stderr_reader.join().unwrap_or_default(),
+    })
+}
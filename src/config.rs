@@ -0,0 +1,523 @@
+//! Configuration of the judge, either built directly by embedding programs
+//! or read from environment variables by the binary.
+
+use std::collections::HashMap;
+use std::env::VarError;
+use std::fmt::Display;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::c_testing::{self, InputMode, Language, TestFilter};
+use crate::cgroup;
+use crate::checker::CheckerFormat;
+use crate::diff::DiffMode;
+use crate::SlightlyWrongPolicy;
+
+/// Manifest in the tests directory with settings of the problem, keyed by
+/// names of the environment variables, which override them.
+const PROBLEM_MANIFEST_NAME: &str = "problem.json";
+const DEFAULT_TESTS_PATH: &str = "/tests";
+
+/// Where and how the result and progress are written.
+#[derive(Clone)]
+pub struct OutputConfig {
+    pub dir: PathBuf,
+    /// Name of the result file in `dir`.
+    pub result_name: String,
+    pub pretty_json: bool,
+    /// Results of single tests are also streamed here as they finish,
+    /// `-` streams them to stdout.
+    pub ndjson_path: Option<PathBuf>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> OutputConfig {
+        OutputConfig {
+            dir: "/output".into(),
+            result_name: "result.json".into(),
+            pretty_json: false,
+            ndjson_path: None,
+        }
+    }
+}
+
+/// Everything the judge needs to know about a single testing.
+#[derive(Clone)]
+pub struct JudgeConfig {
+    pub language: &'static Language,
+    pub output: OutputConfig,
+    pub tests_path: PathBuf,
+    /// Extensions of test inputs and expected outputs, without the dot.
+    pub input_extension: String,
+    pub output_extension: String,
+    pub program_path: PathBuf,
+    /// Timeout of tests without their own `N.timeout` file.
+    pub timeout_millis: u64,
+    /// Time the program has to exit after SIGTERM on timeout before it is
+    /// killed, it is killed right away when zero.
+    pub timeout_grace_millis: u64,
+    /// Memory limit of tested program in MiB. No limit is enforced when unset.
+    pub memory_limit_mib: Option<u64>,
+    /// Maximal number of processes of the tested program, enforced by the
+    /// pids controller of its cgroup. Without one, it is the `RLIMIT_NPROC`
+    /// of the program, which counts all processes of the user running it,
+    /// including the judge's threads, and does not limit root at all. No limit
+    /// is set when unset.
+    pub process_limit: Option<u64>,
+    pub compile_timeout_millis: u64,
+    /// Address space limit of the compiler in MiB. No limit is set when unset.
+    pub compile_memory_limit_mib: Option<u64>,
+    /// Flags passed to the compiler instead of language defaults.
+    pub compile_flags: Option<Vec<String>>,
+    /// Maximal number of bytes the program may output.
+    pub max_output_bytes: u64,
+    pub stop_on_first_failure: bool,
+    /// Only tests with matching ids are run, all of them when unset.
+    pub test_filter: Option<TestFilter>,
+    /// Wall time in milliseconds for running all tests, tests not started
+    /// until it expires are skipped.
+    pub global_timeout_millis: Option<u64>,
+    pub diff_mode: DiffMode,
+    /// Numbers differing by at most the absolute epsilon, or the relative
+    /// epsilon times the expected number, are equal in float diff mode.
+    pub float_absolute_epsilon: f64,
+    pub float_relative_epsilon: f64,
+    /// Significant figures compared in significant figures diff mode.
+    pub significant_figures: usize,
+    /// Program judging the output instead of comparing it with the expected one.
+    pub checker_path: Option<PathBuf>,
+    pub checker_format: CheckerFormat,
+    /// Program talking to the tested program instead of a static input,
+    /// interactive mode is enabled by setting it.
+    pub interactor_path: Option<PathBuf>,
+    /// Number of retries of spawning the program after transient failures.
+    pub spawn_retries: u32,
+    /// Number of tests run at the same time.
+    pub parallel_jobs: NonZeroUsize,
+    pub slightly_wrong_policy: SlightlyWrongPolicy,
+    /// Part of test's points awarded for slightly wrong output under the
+    /// partial policy.
+    pub slightly_wrong_points_fraction: f64,
+    pub input_mode: InputMode,
+    /// Input not ending with a newline gets one before it is passed to the program.
+    pub append_input_newline: bool,
+    /// Name of the file program reads its input from in file input mode.
+    pub input_file_name: String,
+    /// Name of the file program writes its output to in file input mode.
+    pub output_file_name: String,
+    /// Maximal number of characters of each reported output, longer ones
+    /// keep only their start and end.
+    pub max_diff_chars: usize,
+    /// Outputs of failed hidden tests are left out of the result.
+    pub hide_hidden_outputs: bool,
+    /// Environment of the tested program besides `PATH`, nothing else of the
+    /// judge's environment is passed to it.
+    pub program_env: Vec<(String, String)>,
+    /// Runs tested program under the restricted profile.
+    pub sandbox: bool,
+    /// Only validates the configuration and tests.
+    pub dry_run: bool,
+    /// Submission is only compiled, tests are not even looked for.
+    pub compile_only: bool,
+}
+
+impl JudgeConfig {
+    /// Configuration with defaults for everything but the language and timeout,
+    /// the same ones `from_env` uses for unset variables.
+    pub fn new(language: &'static Language, timeout_millis: u64) -> JudgeConfig {
+        JudgeConfig {
+            language,
+            output: OutputConfig::default(),
+            tests_path: DEFAULT_TESTS_PATH.into(),
+            input_extension: "in".into(),
+            output_extension: "out".into(),
+            program_path: "/program".into(),
+            timeout_millis,
+            timeout_grace_millis: 0,
+            memory_limit_mib: None,
+            process_limit: None,
+            compile_timeout_millis: 30_000,
+            compile_memory_limit_mib: None,
+            compile_flags: None,
+            max_output_bytes: 64 * 1024 * 1024,
+            stop_on_first_failure: true,
+            test_filter: None,
+            global_timeout_millis: None,
+            diff_mode: DiffMode::Exact,
+            float_absolute_epsilon: 1e-6,
+            float_relative_epsilon: 1e-6,
+            significant_figures: 6,
+            checker_path: None,
+            checker_format: CheckerFormat::Stdin,
+            interactor_path: None,
+            spawn_retries: 2,
+            parallel_jobs: NonZeroUsize::MIN,
+            slightly_wrong_policy: SlightlyWrongPolicy::Partial,
+            slightly_wrong_points_fraction: 0.0,
+            input_mode: InputMode::Stdin,
+            append_input_newline: false,
+            input_file_name: "input.txt".into(),
+            output_file_name: "output.txt".into(),
+            max_diff_chars: 10_000,
+            hide_hidden_outputs: false,
+            program_env: Vec::new(),
+            sandbox: false,
+            dry_run: false,
+            compile_only: false,
+        }
+    }
+
+    /// Reads and validates every variable up front, so that configuration
+    /// problems are reported in the result instead of panicking in the
+    /// middle of testing. All problems found are reported at once.
+    ///
+    /// Variables not set in the environment are taken from `problem.json` in
+    /// the tests directory, if there is one.
+    pub fn from_env() -> Result<JudgeConfig, ConfigError> {
+        let mut env = EnvReader::default();
+        let tests_path = env.load_problem_manifest()?;
+
+        let language = env.required::<String>("TEST_LANGUAGE").and_then(|name| {
+            log::info!("Selected language {name}.");
+            if name == CUSTOM_LANGUAGE {
+                return custom_language(&mut env);
+            }
+
+            let language = Language::from_name(&name);
+            if language.is_none() {
+                env.errors.push(ConfigError::UnsupportedLanguage(name));
+            }
+            language
+        });
+        let timeout_millis = env.required("TIMEOUT_TIME");
+
+        // Placeholders are only kept when an error is returned below.
+        let mut config = JudgeConfig::new(
+            language.unwrap_or(&c_testing::C_LANGUAGE),
+            timeout_millis.unwrap_or_default(),
+        );
+
+        config.output = OutputConfig::read(&mut env);
+        if let Some(tests_path) = tests_path {
+            config.tests_path = tests_path;
+        }
+        env.read(&mut config.input_extension, "INPUT_EXT");
+        env.read(&mut config.output_extension, "OUTPUT_EXT");
+        // Inputs and expected outputs could not be told apart otherwise.
+        if config.input_extension.is_empty() || config.input_extension == config.output_extension {
+            env.errors.push(ConfigError::Malformed("INPUT_EXT"));
+        }
+        if config.output_extension.is_empty() {
+            env.errors.push(ConfigError::Malformed("OUTPUT_EXT"));
+        }
+        env.read(&mut config.program_path, "PROGRAM_DIR");
+        env.read(&mut config.timeout_grace_millis, "TIMEOUT_GRACE");
+        config.memory_limit_mib = env.optional("MEMORY_LIMIT");
+        config.process_limit = env.optional("PROCESS_LIMIT");
+        // Limit would silently not apply to anything.
+        if config.process_limit.is_some()
+            && unsafe { libc::geteuid() } == 0
+            && !cgroup::processes_limitable()
+        {
+            env.errors.push(ConfigError::Unenforceable("PROCESS_LIMIT"));
+        }
+        env.read(&mut config.compile_timeout_millis, "COMPILE_TIMEOUT");
+        config.compile_memory_limit_mib = env.optional("COMPILE_MEMORY_LIMIT");
+        config.compile_flags = env
+            .optional::<String>("COMPILE_FLAGS")
+            .map(|flags| flags.split_whitespace().map(String::from).collect());
+        env.read(&mut config.max_output_bytes, "MAX_OUTPUT_BYTES");
+        config.stop_on_first_failure =
+            env.toggle_or("STOP_ON_FIRST_FAILURE", config.stop_on_first_failure);
+        config.global_timeout_millis = env.optional("GLOBAL_TIMEOUT");
+        config.test_filter = env.optional("TEST_FILTER");
+        env.read(&mut config.diff_mode, "DIFF_MODE");
+        // `FLOAT_EPSILON` is the older name of the absolute epsilon.
+        env.read(&mut config.float_absolute_epsilon, "FLOAT_EPSILON");
+        env.read(&mut config.float_absolute_epsilon, "FLOAT_ABS_EPS");
+        env.read(&mut config.float_relative_epsilon, "FLOAT_REL_EPS");
+        env.read(&mut config.significant_figures, "FLOAT_SIG_FIGS");
+        if config.significant_figures == 0 {
+            env.errors.push(ConfigError::Malformed("FLOAT_SIG_FIGS"));
+        }
+        config.checker_path = env.optional("CHECKER_PATH");
+        env.read(&mut config.checker_format, "CHECKER_FORMAT");
+        if env.toggle("INTERACTIVE") {
+            config.interactor_path = env.required("INTERACTOR_PATH");
+        }
+        env.read(&mut config.spawn_retries, "SPAWN_RETRIES");
+        env.read(&mut config.parallel_jobs, "PARALLEL_JOBS");
+        env.read(&mut config.slightly_wrong_policy, "TREAT_SLIGHTLY_WRONG_AS");
+        env.read(
+            &mut config.slightly_wrong_points_fraction,
+            "SLIGHTLY_WRONG_POINTS_FRACTION",
+        );
+        env.read(&mut config.input_mode, "INPUT_MODE");
+        config.append_input_newline = env.toggle("APPEND_INPUT_NEWLINE");
+        env.read(&mut config.input_file_name, "INPUT_FILE_NAME");
+        env.read(&mut config.output_file_name, "OUTPUT_FILE_NAME");
+        env.read(&mut config.max_diff_chars, "MAX_DIFF_CHARS");
+        config.hide_hidden_outputs = env.toggle("HIDE_HIDDEN_OUTPUTS");
+        if let Some(program_env) = env.optional::<String>("PROGRAM_ENV") {
+            match parse_program_env(&program_env) {
+                Some(program_env) => config.program_env = program_env,
+                None => env.errors.push(ConfigError::Malformed("PROGRAM_ENV")),
+            }
+        }
+        config.sandbox = env.toggle("SANDBOX");
+        config.dry_run = env.toggle("DRY_RUN");
+        config.compile_only = env.toggle("COMPILE_ONLY");
+
+        for name in env.manifest.keys() {
+            log::warn!("Setting {name} of {PROBLEM_MANIFEST_NAME} is not used.");
+        }
+
+        match env.errors.len() {
+            0 => Ok(config),
+            1 => Err(env.errors.remove(0)),
+            _ => Err(ConfigError::Several(env.errors)),
+        }
+    }
+}
+
+/// Parses whitespace separated `NAME=value` entries, a bare `NAME` passes
+/// the judge's own value of the variable if it has one.
+fn parse_program_env(entries: &str) -> Option<Vec<(String, String)>> {
+    let mut program_env = Vec::new();
+
+    for entry in entries.split_whitespace() {
+        let (name, value) = match entry.split_once('=') {
+            Some((name, value)) => (name, value.to_string()),
+            None => match std::env::var(entry) {
+                Ok(value) => (entry, value),
+                Err(_) => continue,
+            },
+        };
+        if name.is_empty() {
+            return None;
+        }
+
+        program_env.push((name.to_string(), value));
+    }
+
+    Some(program_env)
+}
+
+/// Language selected by `TEST_LANGUAGE=CUSTOM`, run by `RUN_CMD` and
+/// compiled by `COMPILE_CMD` if set.
+const CUSTOM_LANGUAGE: &str = "CUSTOM";
+
+/// Custom language lives until the end of the process, like the built-in ones.
+fn custom_language(env: &mut EnvReader) -> Option<&'static Language> {
+    let split =
+        |command: String| -> Vec<String> { command.split_whitespace().map(String::from).collect() };
+
+    let source_name = env.required::<String>("SOURCE_NAME");
+    let compile = env.optional::<String>("COMPILE_CMD").map(split);
+    let run = env.required::<String>("RUN_CMD").map(split);
+
+    // Commands without even a program can not be run, arguments of tests
+    // may be missing.
+    let runnable = |command: &Vec<String>| {
+        command
+            .first()
+            .is_some_and(|program| program != c_testing::ARGS_PLACEHOLDER)
+    };
+    for (name, command) in [("COMPILE_CMD", &compile), ("RUN_CMD", &run)] {
+        if command.as_ref().is_some_and(|command| !runnable(command)) {
+            env.errors.push(ConfigError::Malformed(name));
+        }
+    }
+    if compile.as_ref().is_some_and(|compile| !runnable(compile)) {
+        return None;
+    }
+
+    Some(Box::leak(Box::new(Language::Custom {
+        source_name: source_name?,
+        compile,
+        run: run.filter(runnable)?,
+    })))
+}
+
+impl OutputConfig {
+    /// Reads the same variables and manifest as `JudgeConfig::from_env`, but
+    /// invalid values are replaced with defaults, so that even configuration
+    /// problems get reported somewhere.
+    pub fn from_env() -> OutputConfig {
+        let mut env = EnvReader::default();
+        // Settings of a broken manifest are left out, the problem with it is
+        // what gets reported.
+        if env.load_problem_manifest().is_err() {
+            env = EnvReader::default();
+        }
+
+        OutputConfig::read(&mut env)
+    }
+
+    fn read(env: &mut EnvReader) -> OutputConfig {
+        let mut output = OutputConfig::default();
+
+        env.read(&mut output.dir, "OUTPUT_DIR");
+        env.read(&mut output.result_name, "RESULT_FILE_NAME");
+        output.pretty_json = env.toggle("PRETTY_JSON");
+        output.ndjson_path = env.optional("NDJSON_OUTPUT");
+
+        output
+    }
+}
+
+/// Problem with an environment variable, identified by its name.
+#[derive(Debug)]
+pub enum ConfigError {
+    Missing(&'static str),
+    Malformed(&'static str),
+    NotUnicode(&'static str),
+    UnsupportedLanguage(String),
+    /// Limit which the judge running as root can not enforce without cgroups.
+    Unenforceable(&'static str),
+    /// Problem manifest could not be read or parsed.
+    Manifest(String),
+    Several(Vec<ConfigError>),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing(name) => write!(f, "Missing environment variable {name}."),
+            Self::Malformed(name) => write!(f, "Unable to parse environment variable {name}."),
+            Self::NotUnicode(name) => {
+                write!(f, "Environment variable {name} is not valid unicode.")
+            }
+            Self::UnsupportedLanguage(name) => write!(f, "Language {name} is not supported."),
+            Self::Unenforceable(name) => write!(
+                f,
+                "Environment variable {name} can not be enforced, the judge runs as root and the cgroup pids controller is not available."
+            ),
+            Self::Manifest(message) => write!(f, "{message}"),
+            Self::Several(errors) => {
+                let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                write!(f, "{}", messages.join(" "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Reads variables while collecting problems with them.
+#[derive(Default)]
+struct EnvReader {
+    errors: Vec<ConfigError>,
+    /// Settings of the problem manifest not read yet.
+    manifest: HashMap<String, String>,
+}
+
+impl EnvReader {
+    /// Loads the manifest of the tests directory if there is one, returns
+    /// the directory when set.
+    fn load_problem_manifest(&mut self) -> Result<Option<PathBuf>, ConfigError> {
+        // The manifest is found through the tests directory, so it can not set it.
+        let tests_path: Option<PathBuf> = self.optional("TESTS_DIR");
+        let manifest = tests_path
+            .as_deref()
+            .unwrap_or(Path::new(DEFAULT_TESTS_PATH))
+            .join(PROBLEM_MANIFEST_NAME);
+        if manifest.is_file() {
+            self.load_manifest(&manifest)?;
+        }
+
+        Ok(tests_path)
+    }
+
+    /// Manifest is a JSON object of strings, numbers and booleans, the latter
+    /// two are read as their JSON text.
+    fn load_manifest(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let name = path.display();
+        let content = std::fs::read_to_string(path)
+            .map_err(|_| ConfigError::Manifest(format!("Problem while reading {name}.")))?;
+        let settings: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&content)
+            .map_err(|error| {
+            ConfigError::Manifest(format!("Unable to parse {name}: {error}."))
+        })?;
+
+        for (key, value) in settings {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                serde_json::Value::Number(value) => value.to_string(),
+                serde_json::Value::Bool(value) => value.to_string(),
+                _ => {
+                    return Err(ConfigError::Manifest(format!(
+                        "Setting {key} of {name} is not a string, number or boolean."
+                    )))
+                }
+            };
+            self.manifest.insert(key, value);
+        }
+
+        log::info!("Loaded problem manifest {name}.");
+        Ok(())
+    }
+
+    /// Environment takes precedence over the manifest.
+    fn var(&mut self, name: &'static str) -> Result<String, VarError> {
+        let manifest_value = self.manifest.remove(name);
+
+        match std::env::var(name) {
+            Err(VarError::NotPresent) => manifest_value.ok_or(VarError::NotPresent),
+            value => value,
+        }
+    }
+
+    fn optional<T: FromStr>(&mut self, name: &'static str) -> Option<T> {
+        let error = match self.var(name) {
+            Ok(value) => match value.parse::<T>() {
+                Ok(parsed) => return Some(parsed),
+                Err(_) => ConfigError::Malformed(name),
+            },
+            Err(VarError::NotPresent) => return None,
+            Err(VarError::NotUnicode(_)) => ConfigError::NotUnicode(name),
+        };
+
+        self.errors.push(error);
+        None
+    }
+
+    fn required<T: FromStr>(&mut self, name: &'static str) -> Option<T> {
+        if std::env::var_os(name).is_none() && !self.manifest.contains_key(name) {
+            self.errors.push(ConfigError::Missing(name));
+            return None;
+        }
+
+        self.optional(name)
+    }
+
+    /// Overwrites `value` when the variable is set.
+    fn read<T: FromStr>(&mut self, value: &mut T, name: &'static str) {
+        if let Some(parsed) = self.optional(name) {
+            *value = parsed;
+        }
+    }
+
+    /// Toggles are enabled by setting them to 1, or to true in the manifest,
+    /// and disabled by 0 or false. Unset toggles are disabled.
+    fn toggle(&mut self, name: &'static str) -> bool {
+        self.toggle_or(name, false)
+    }
+
+    /// Toggle which is `default` when unset.
+    fn toggle_or(&mut self, name: &'static str, default: bool) -> bool {
+        match self.optional::<String>(name).as_deref() {
+            None => default,
+            Some("true") => true,
+            Some("false") => false,
+            Some(value) => match value.parse::<u8>() {
+                Ok(value) => value == 1,
+                Err(_) => {
+                    self.errors.push(ConfigError::Malformed(name));
+                    default
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,552 @@
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Number of lines shown on each side of the first difference.
+const CONTEXT_LINES: usize = 2;
+
+/// How program's output is compared with the expected one.
+#[derive(Clone, Copy)]
+pub enum DiffMode {
+    /// Outputs have to be identical, surrounding whitespace makes it slightly wrong.
+    Exact,
+    /// Outputs are compared token by token, numbers are equal within the
+    /// absolute or the relative epsilon.
+    Float,
+    /// Trailing whitespace of lines and repeated blank lines make output slightly wrong.
+    Whitespace,
+    /// Differences in letter case make output slightly wrong. Surrounding
+    /// whitespace is trimmed, whitespace inside is still compared exactly,
+    /// as modes can not be combined.
+    CaseInsensitive,
+    /// Outputs are compared as sequences of whitespace separated tokens,
+    /// any difference in the layout makes output slightly wrong.
+    Tokens,
+    /// Outputs are compared byte by byte, for problems with binary output.
+    Binary,
+    /// Outputs are compared token by token, numbers are equal when rounded to
+    /// the same number of significant figures.
+    SignificantFigures,
+    /// Outputs are compared as multisets of lines, for problems accepting
+    /// answers in any order. Trailing whitespace of lines makes output
+    /// slightly wrong, blank lines at the end are ignored.
+    UnorderedLines,
+    /// Outputs are compared in increasingly lenient stages, trimmed, as
+    /// tokens and as sorted tokens. The strictest stage matching tells how
+    /// close the slightly wrong output was.
+    Lenient,
+}
+
+impl FromStr for DiffMode {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<DiffMode, ()> {
+        match name {
+            "exact" => Ok(Self::Exact),
+            "float" => Ok(Self::Float),
+            "whitespace" => Ok(Self::Whitespace),
+            "case_insensitive" => Ok(Self::CaseInsensitive),
+            "tokens" => Ok(Self::Tokens),
+            "binary" => Ok(Self::Binary),
+            "unordered_lines" => Ok(Self::UnorderedLines),
+            "significant_figures" => Ok(Self::SignificantFigures),
+            "lenient" => Ok(Self::Lenient),
+            _ => Err(()),
+        }
+    }
+}
+
+pub enum Verdict {
+    Accepted,
+    SlightlyWrong(SlightDifference),
+    Wrong,
+}
+
+/// What makes the output only slightly wrong.
+#[derive(Serialize, Clone, Copy)]
+pub enum SlightDifference {
+    /// Output has more or fewer newlines at its end.
+    TrailingNewline,
+    /// Output differs in whitespace at its end other than just newlines.
+    TrailingWhitespace,
+    LeadingWhitespace,
+    /// Output differs in whitespace at both its start and end.
+    SurroundingWhitespace,
+    /// Lines have different trailing whitespace or there are extra blank lines.
+    LineWhitespace,
+    /// Tokens are the same but separated differently.
+    Layout,
+    LetterCase,
+    /// Tokens are the same but in a different order.
+    TokenOrder,
+}
+
+/// Except in binary mode, outputs are compared as text with invalid UTF-8
+/// replaced, and line endings are normalized and a leading byte order mark
+/// stripped first, so that CRLF and BOM in tests written on Windows never
+/// make the output wrong. Epsilons are only used in float mode,
+/// `significant_figures` in significant figures mode.
+pub fn diff(
+    mode: DiffMode,
+    absolute_epsilon: f64,
+    relative_epsilon: f64,
+    significant_figures: usize,
+    expected: &[u8],
+    got: &[u8],
+) -> Verdict {
+    let text = |bytes: &[u8]| normalize_line_endings(strip_bom(&String::from_utf8_lossy(bytes)));
+
+    match mode {
+        DiffMode::Exact => diff_exact(&text(expected), &text(got)),
+        DiffMode::Float | DiffMode::SignificantFigures => diff_numeric(
+            &text(expected),
+            &text(got),
+            numbers_equal(
+                mode,
+                absolute_epsilon,
+                relative_epsilon,
+                significant_figures,
+            ),
+        ),
+        DiffMode::Whitespace => diff_whitespace(&text(expected), &text(got)),
+        DiffMode::CaseInsensitive => diff_case_insensitive(&text(expected), &text(got)),
+        DiffMode::Tokens => diff_tokens(&text(expected), &text(got)),
+        DiffMode::Binary => diff_binary(expected, got),
+        DiffMode::UnorderedLines => diff_unordered_lines(&text(expected), &text(got)),
+        DiffMode::Lenient => diff_lenient(&text(expected), &text(got)),
+    }
+}
+
+/// Comparison of numeric tokens in numeric modes, in other modes numbers
+/// have to be written the same way. Absolute epsilon alone is too strict for
+/// huge numbers and relative alone for numbers close to zero, so a number
+/// within either of them is equal.
+fn numbers_equal(
+    mode: DiffMode,
+    absolute_epsilon: f64,
+    relative_epsilon: f64,
+    significant_figures: usize,
+) -> Box<dyn Fn(f64, f64) -> bool> {
+    match mode {
+        DiffMode::Float => Box::new(move |expected, got| {
+            let difference = (expected - got).abs();
+            difference <= absolute_epsilon || difference <= relative_epsilon * expected.abs()
+        }),
+        DiffMode::SignificantFigures => Box::new(move |expected, got| {
+            round_significant(expected, significant_figures)
+                == round_significant(got, significant_figures)
+        }),
+        _ => Box::new(|_, _| false),
+    }
+}
+
+/// First pair of tokens on which outputs differ, the token of the output
+/// which ended earlier is missing.
+pub struct TokenDifference {
+    /// Counted from 1, like lines.
+    pub index: u64,
+    pub expected: Option<String>,
+    pub got: Option<String>,
+}
+
+/// Finds the first differing token in modes comparing outputs token by
+/// token. Returns `None` in other modes and when all tokens are equal.
+pub fn first_token_difference(
+    mode: DiffMode,
+    absolute_epsilon: f64,
+    relative_epsilon: f64,
+    significant_figures: usize,
+    expected: &str,
+    got: &str,
+) -> Option<TokenDifference> {
+    if !matches!(
+        mode,
+        DiffMode::Tokens | DiffMode::Float | DiffMode::SignificantFigures | DiffMode::Lenient
+    ) {
+        return None;
+    }
+
+    let numbers_equal = numbers_equal(
+        mode,
+        absolute_epsilon,
+        relative_epsilon,
+        significant_figures,
+    );
+    let mut expected_tokens = strip_bom(expected).split_whitespace();
+    let mut got_tokens = strip_bom(got).split_whitespace();
+
+    for index in 1.. {
+        match (expected_tokens.next(), got_tokens.next()) {
+            (None, None) => return None,
+            (Some(expected), Some(got)) if numeric_tokens_equal(expected, got, &numbers_equal) => {}
+            (expected, got) => {
+                return Some(TokenDifference {
+                    index,
+                    expected: expected.map(String::from),
+                    got: got.map(String::from),
+                })
+            }
+        }
+    }
+
+    None
+}
+
+/// Likely reason of a wrong output, shown as feedback.
+#[derive(Serialize, Clone, Copy)]
+pub enum Hint {
+    /// Output is a proper prefix of the expected one, the program probably
+    /// did not finish printing its answer.
+    TruncatedOutput,
+}
+
+/// Only called for wrong outputs, which are never identical to the expected.
+pub fn hint(expected: &str, got: &str) -> Option<Hint> {
+    let expected = normalize_line_endings(expected);
+    let got = normalize_line_endings(got);
+
+    (!got.is_empty() && got.len() < expected.len() && expected.starts_with(&got))
+        .then_some(Hint::TruncatedOutput)
+}
+
+/// Lines around the first difference of both outputs.
+#[derive(Serialize, Clone)]
+pub struct DiffContext {
+    pub expected: String,
+    pub got: String,
+}
+
+/// Finds the first line (counted from 1) on which outputs differ, together
+/// with a few lines around it, each shortened to `max_chars` characters.
+/// Returns `None` for identical outputs.
+pub fn first_difference(expected: &str, got: &str, max_chars: usize) -> Option<(u64, DiffContext)> {
+    if expected == got {
+        return None;
+    }
+
+    let expected_lines: Vec<&str> = expected.lines().collect();
+    let got_lines: Vec<&str> = got.lines().collect();
+    let line_count = expected_lines.len().max(got_lines.len());
+
+    // Outputs differing only in a trailing newline differ on their last line.
+    let index = (0..line_count)
+        .find(|&index| expected_lines.get(index) != got_lines.get(index))
+        .unwrap_or(line_count.saturating_sub(1));
+
+    let context = DiffContext {
+        expected: context_around(&expected_lines, index, max_chars),
+        got: context_around(&got_lines, index, max_chars),
+    };
+
+    Some((index as u64 + 1, context))
+}
+
+/// Keeps at most `max_chars` characters of the text, taken from its start
+/// and end, with a marker saying how much was left out in between.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    let length = text.chars().count();
+    if length <= max_chars {
+        return text.to_owned();
+    }
+
+    let head: String = text.chars().take(max_chars / 2).collect();
+    let tail: String = text
+        .chars()
+        .skip(length - (max_chars - max_chars / 2))
+        .collect();
+
+    format!(
+        "{head}...[{} characters omitted]...{tail}",
+        length - max_chars
+    )
+}
+
+fn context_around(lines: &[&str], index: usize, max_chars: usize) -> String {
+    let start = index.saturating_sub(CONTEXT_LINES).min(lines.len());
+    let end = (index + CONTEXT_LINES + 1).min(lines.len());
+
+    lines[start..end]
+        .iter()
+        .map(|line| truncate(line, max_chars))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn normalize_line_endings(text: &str) -> String {
+    text.replace("\r\n", "\n")
+}
+
+fn strip_bom(text: &str) -> &str {
+    text.strip_prefix('\u{FEFF}').unwrap_or(text)
+}
+
+fn diff_exact(expected: &str, got: &str) -> Verdict {
+    if expected == got {
+        return Verdict::Accepted;
+    }
+
+    if expected.trim() == got.trim() {
+        return Verdict::SlightlyWrong(surrounding_difference(expected, got));
+    }
+
+    Verdict::Wrong
+}
+
+/// Classifies difference of outputs which are equal once trimmed.
+fn surrounding_difference(expected: &str, got: &str) -> SlightDifference {
+    let leading_differs = leading(expected) != leading(got);
+    let trailing_differs = trailing(expected) != trailing(got);
+
+    match (leading_differs, trailing_differs) {
+        (true, true) => SlightDifference::SurroundingWhitespace,
+        (true, false) => SlightDifference::LeadingWhitespace,
+        _ if trailing(expected).replace('\n', "") == trailing(got).replace('\n', "") => {
+            SlightDifference::TrailingNewline
+        }
+        _ => SlightDifference::TrailingWhitespace,
+    }
+}
+
+fn leading(text: &str) -> &str {
+    &text[..text.len() - text.trim_start().len()]
+}
+
+fn trailing(text: &str) -> &str {
+    &text[text.trim_end().len()..]
+}
+
+fn diff_binary(expected: &[u8], got: &[u8]) -> Verdict {
+    if expected == got {
+        Verdict::Accepted
+    } else {
+        Verdict::Wrong
+    }
+}
+
+fn diff_unordered_lines(expected: &str, got: &str) -> Verdict {
+    if sorted_lines(expected, |line| line) == sorted_lines(got, |line| line) {
+        return Verdict::Accepted;
+    }
+
+    if sorted_lines(expected, str::trim_end) == sorted_lines(got, str::trim_end) {
+        return Verdict::SlightlyWrong(SlightDifference::LineWhitespace);
+    }
+
+    Verdict::Wrong
+}
+
+/// Lines of the text after `normalize`, sorted, without blank lines at the end.
+fn sorted_lines<'a>(text: &'a str, normalize: impl Fn(&'a str) -> &'a str) -> Vec<&'a str> {
+    let mut lines: Vec<&str> = text.trim_end_matches('\n').lines().map(normalize).collect();
+    lines.sort_unstable();
+    lines
+}
+
+fn diff_whitespace(expected: &str, got: &str) -> Verdict {
+    if expected == got {
+        return Verdict::Accepted;
+    }
+
+    if normalize_whitespace(expected) == normalize_whitespace(got) {
+        return Verdict::SlightlyWrong(SlightDifference::LineWhitespace);
+    }
+
+    Verdict::Wrong
+}
+
+fn diff_case_insensitive(expected: &str, got: &str) -> Verdict {
+    if expected == got {
+        return Verdict::Accepted;
+    }
+
+    if expected.trim() == got.trim() {
+        return Verdict::SlightlyWrong(surrounding_difference(expected, got));
+    }
+
+    if expected.trim().to_lowercase() == got.trim().to_lowercase() {
+        return Verdict::SlightlyWrong(SlightDifference::LetterCase);
+    }
+
+    Verdict::Wrong
+}
+
+fn diff_tokens(expected: &str, got: &str) -> Verdict {
+    if expected == got {
+        return Verdict::Accepted;
+    }
+
+    let expected_tokens: Vec<&str> = expected.split_whitespace().collect();
+    let got_tokens: Vec<&str> = got.split_whitespace().collect();
+
+    if expected_tokens == got_tokens {
+        return Verdict::SlightlyWrong(SlightDifference::Layout);
+    }
+
+    Verdict::Wrong
+}
+
+fn diff_lenient(expected: &str, got: &str) -> Verdict {
+    match diff_exact(expected, got) {
+        Verdict::Wrong => {}
+        verdict => return verdict,
+    }
+
+    let mut expected_tokens: Vec<&str> = expected.split_whitespace().collect();
+    let mut got_tokens: Vec<&str> = got.split_whitespace().collect();
+
+    if expected_tokens == got_tokens {
+        return Verdict::SlightlyWrong(SlightDifference::Layout);
+    }
+
+    expected_tokens.sort_unstable();
+    got_tokens.sort_unstable();
+    if expected_tokens == got_tokens {
+        return Verdict::SlightlyWrong(SlightDifference::TokenOrder);
+    }
+
+    Verdict::Wrong
+}
+
+/// Strips trailing whitespace of every line, collapses runs of blank lines
+/// and drops blank lines at the end.
+fn normalize_whitespace(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() && lines.last().is_some_and(|last| last.is_empty()) {
+            continue;
+        }
+
+        lines.push(line);
+    }
+
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+
+    lines.join("\n")
+}
+
+fn diff_numeric(expected: &str, got: &str, numbers_equal: impl Fn(f64, f64) -> bool) -> Verdict {
+    let expected_tokens: Vec<&str> = expected.split_whitespace().collect();
+    let got_tokens: Vec<&str> = got.split_whitespace().collect();
+
+    if expected_tokens.len() != got_tokens.len() {
+        return Verdict::Wrong;
+    }
+
+    let all_equal = expected_tokens
+        .iter()
+        .zip(got_tokens.iter())
+        .all(|(expected, got)| numeric_tokens_equal(expected, got, &numbers_equal));
+
+    if all_equal {
+        Verdict::Accepted
+    } else {
+        Verdict::Wrong
+    }
+}
+
+/// Non-numeric tokens have to be identical, numeric ones are compared by
+/// `numbers_equal`.
+fn numeric_tokens_equal(
+    expected: &str,
+    got: &str,
+    numbers_equal: impl Fn(f64, f64) -> bool,
+) -> bool {
+    if expected == got {
+        return true;
+    }
+
+    match (expected.parse::<f64>(), got.parse::<f64>()) {
+        (Ok(expected), Ok(got)) => numbers_equal(expected, got),
+        _ => false,
+    }
+}
+
+/// Number rounded to `figures` significant figures in scientific notation,
+/// e.g. `1.23e6` for both `1234500` and `1.2345e6` with three figures.
+/// Negative zero is formatted as zero.
+fn round_significant(number: f64, figures: usize) -> String {
+    format!("{:.*e}", figures.saturating_sub(1), number + 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepted(mode: DiffMode, expected: &str, got: &str) -> bool {
+        matches!(
+            diff(mode, 1e-6, 1e-6, 6, expected.as_bytes(), got.as_bytes()),
+            Verdict::Accepted
+        )
+    }
+
+    fn slightly_wrong_exact(expected: &str, got: &str) -> Option<SlightDifference> {
+        match diff(
+            DiffMode::Exact,
+            0.0,
+            0.0,
+            6,
+            expected.as_bytes(),
+            got.as_bytes(),
+        ) {
+            Verdict::SlightlyWrong(difference) => Some(difference),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn negative_zero_equals_zero_in_significant_figures() {
+        assert!(accepted(
+            DiffMode::SignificantFigures,
+            "0.000\n",
+            "-0.000\n"
+        ));
+        assert!(accepted(DiffMode::SignificantFigures, "-0.0\n", "0\n"));
+    }
+
+    #[test]
+    fn mixed_line_endings_are_accepted() {
+        assert!(accepted(DiffMode::Exact, "1\r\n2\n3\r\n", "1\n2\r\n3\n"));
+        assert!(accepted(
+            DiffMode::Whitespace,
+            "a b\r\n\r\nc\n",
+            "a b\n\nc\r\n"
+        ));
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_slightly_wrong_on_either_side() {
+        for (expected, got) in [("42\n", "  42\n"), ("  42\n", "42\n")] {
+            assert!(matches!(
+                slightly_wrong_exact(expected, got),
+                Some(SlightDifference::LeadingWhitespace)
+            ));
+        }
+        for (expected, got) in [("42\n", "42  \n"), ("42  \n", "42\n")] {
+            assert!(matches!(
+                slightly_wrong_exact(expected, got),
+                Some(SlightDifference::TrailingWhitespace)
+            ));
+        }
+        for (expected, got) in [("42\n", "42"), ("42", "42\n")] {
+            assert!(matches!(
+                slightly_wrong_exact(expected, got),
+                Some(SlightDifference::TrailingNewline)
+            ));
+        }
+        for (expected, got) in [("42\n", " 42 \n"), (" 42 \n", "42\n")] {
+            assert!(matches!(
+                slightly_wrong_exact(expected, got),
+                Some(SlightDifference::SurroundingWhitespace)
+            ));
+        }
+    }
+
+    #[test]
+    fn byte_order_mark_is_ignored() {
+        assert!(accepted(DiffMode::Exact, "\u{FEFF}42\n", "42\n"));
+        assert!(accepted(DiffMode::Tokens, "\u{FEFF}1 2\n", "1 2\n"));
+        assert!(accepted(DiffMode::Exact, "42\n", "\u{FEFF}42\n"));
+    }
+}
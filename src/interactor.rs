@@ -0,0 +1,89 @@
+use std::{
+    io::Read,
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use wait_timeout::ChildExt;
+
+use crate::checker::CheckerVerdict;
+
+/// Interactor running alongside the tested program.
+pub struct Interaction {
+    process: Child,
+    stderr_reader: JoinHandle<String>,
+}
+
+/// Runs interactor as `interactor <input file> [expected output file]` with its
+/// stdin connected to program's stdout and its stdout to program's stdin.
+/// Exit code 0 accepts the interaction, any other rejects it and interactor's
+/// stderr explains why.
+pub fn spawn(
+    interactor: &Path,
+    in_file: &Path,
+    out_file: Option<&Path>,
+    program: &mut Child,
+) -> Result<Interaction, String> {
+    let (Some(program_stdin), Some(program_stdout)) = (program.stdin.take(), program.stdout.take())
+    else {
+        return Err("Program has no pipes to interact through.".into());
+    };
+
+    let mut process = match Command::new(interactor)
+        .arg(in_file)
+        .args(out_file)
+        .stdin(program_stdout)
+        .stdout(program_stdin)
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(process) => process,
+        Err(_) => {
+            return Err("Problem while starting the interactor.".into());
+        }
+    };
+
+    let stderr = process.stderr.take();
+    let stderr_reader = std::thread::spawn(move || {
+        let mut content = String::new();
+        if let Some(mut stderr) = stderr {
+            let _ = stderr.read_to_string(&mut content);
+        }
+        content
+    });
+
+    Ok(Interaction {
+        process,
+        stderr_reader,
+    })
+}
+
+/// Waits at most `timeout` for the interactor to decide, after the program
+/// has already finished.
+pub fn verdict(mut interaction: Interaction, timeout: Duration) -> Result<CheckerVerdict, String> {
+    let status = match interaction.process.wait_timeout(timeout) {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            stop(interaction);
+            return Err("Interactor did not finish after the program exited.".into());
+        }
+        Err(_) => {
+            return Err("Problem while waiting for the interactor.".into());
+        }
+    };
+    let message = interaction.stderr_reader.join().unwrap_or_default();
+
+    match status.code() {
+        Some(0) => Ok(CheckerVerdict::Accepted),
+        Some(_) => Ok(CheckerVerdict::Rejected(message)),
+        None => Err("Interactor terminated by signal.".into()),
+    }
+}
+
+/// Kills the interactor when the program failed and there is nothing to decide.
+pub fn stop(mut interaction: Interaction) {
+    let _ = interaction.process.kill();
+    let _ = interaction.process.wait();
+}
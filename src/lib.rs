@@ -0,0 +1,572 @@
+//! Judge testing a submission against a directory of tests. The
+//! `alsit_testing` binary configures it from environment variables, other
+//! programs can embed it through [`run_submission`].
+
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+mod c_testing;
+mod cgroup;
+mod checker;
+mod config;
+mod diff;
+mod interactor;
+mod sandbox;
+mod termination;
+
+pub use c_testing::{InputMode, Language, TestFilter};
+pub use checker::CheckerFormat;
+pub use config::{ConfigError, JudgeConfig, OutputConfig};
+pub use diff::{DiffContext, DiffMode, Hint, SlightDifference};
+
+const PROGRESS_NAME: &str = "progress.json";
+/// Version of the result format, increased on every change consumers
+/// have to know about.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Makes SIGTERM and SIGINT stop the testing early instead of killing the
+/// process, so that `run_submission` still returns the tests finished so far.
+pub fn handle_termination_signals() -> std::io::Result<()> {
+    termination::install()
+}
+
+/// Exit codes, graded submissions exit with 0 whatever their outcome.
+const EXIT_INTERNAL_PROBLEM: i32 = 2;
+const EXIT_COMPILATION_PROBLEM: i32 = 3;
+
+/// Compiles the submission and runs it on all tests, or only validates
+/// everything in a dry run. Progress is written to the output directory on
+/// the way, the result itself is only returned.
+pub fn run_submission(config: JudgeConfig) -> ProgramResult {
+    if config.dry_run {
+        c_testing::validate(&config)
+    } else {
+        c_testing::invoke_testing(&config)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestingOutcome {
+    Success,
+    Timeout,
+    MemoryExceeded,
+    OutputLimitExceeded,
+    WrongOutput,
+    SlightlyWrongOutput,
+    NoOutput,
+    NoOutputFile,
+    RuntimeError,
+    Skipped,
+    InternalError,
+}
+
+#[derive(Serialize, Clone)]
+pub enum TestLog {
+    /// `time` is the wall time in milliseconds, `memory` the peak memory
+    /// usage in MiB.
+    Success {
+        time: u64,
+        memory: f64,
+    },
+    /// `time` is the wall time in milliseconds when the program was killed.
+    /// Program which exited by itself within `TIMEOUT_GRACE` after SIGTERM
+    /// was probably only slightly too slow.
+    Timeout {
+        time_limit_millis: u64,
+        time: u64,
+        exited_after_term: bool,
+    },
+    MemoryExceeded {
+        memory_used: f64,
+    },
+    OutputLimitExceeded {
+        limit_bytes: u64,
+    },
+    /// Outputs and lines of the context are shortened to `MAX_DIFF_CHARS`,
+    /// lengths in characters are those of whole outputs. Outputs are missing
+    /// in interactive mode and when hidden.
+    /// First differing token, counted from 1, is only found in diff modes
+    /// comparing tokens, a token is missing when its output ended earlier.
+    WrongOutput {
+        expected: Option<String>,
+        got: Option<String>,
+        expected_length: Option<usize>,
+        got_length: Option<usize>,
+        first_diff_line: Option<u64>,
+        context: Option<diff::DiffContext>,
+        first_diff_token_index: Option<u64>,
+        expected_token: Option<String>,
+        got_token: Option<String>,
+        stderr: String,
+        checker_message: Option<String>,
+        hint: Option<diff::Hint>,
+    },
+    SlightlyWrongOutput {
+        expected: Option<String>,
+        got: Option<String>,
+        expected_length: usize,
+        got_length: usize,
+        difference: diff::SlightDifference,
+    },
+    /// Program printed nothing but whitespace while something was expected.
+    NoOutput {
+        stderr: String,
+    },
+    /// Program did not create its output file in file input mode.
+    NoOutputFile {
+        file_name: String,
+        stderr: String,
+    },
+    /// Program terminated abnormally, by a signal or with an exit code.
+    /// Whether it failed to start a process for exceeding the process limit
+    /// is only known when the limit is enforced by the cgroup of the program.
+    RuntimeError {
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+        signal_name: Option<String>,
+        process_limit_reached: bool,
+        stderr: String,
+    },
+    /// Test was not run, or not finished, as the global timeout of the
+    /// testing expired or the judge was asked to terminate.
+    Skipped,
+    InternalError(String),
+}
+
+impl TestingOutcome {
+    /// Higher value means worse outcome. From the worst: internal error, skipped,
+    /// timeout, memory exceeded, output limit exceeded, runtime error, no output
+    /// file, no output, wrong output, slightly wrong output, success.
+    pub fn severity(&self) -> u8 {
+        match self {
+            Self::Success => 0,
+            Self::SlightlyWrongOutput => 1,
+            Self::WrongOutput => 2,
+            Self::NoOutput => 3,
+            Self::NoOutputFile => 4,
+            Self::RuntimeError => 5,
+            Self::OutputLimitExceeded => 6,
+            Self::MemoryExceeded => 7,
+            Self::Timeout => 8,
+            Self::Skipped => 9,
+            Self::InternalError => 10,
+        }
+    }
+
+    /// Folds outcomes of single tests into the outcome of the whole testing,
+    /// which is the worst of them or success if there are none.
+    pub fn aggregate(outcomes: impl IntoIterator<Item = TestingOutcome>) -> TestingOutcome {
+        outcomes
+            .into_iter()
+            .fold(TestingOutcome::Success, |worst, outcome| {
+                if outcome.severity() > worst.severity() {
+                    outcome
+                } else {
+                    worst
+                }
+            })
+    }
+}
+
+/// How slightly wrong output counts in the score and outcome of the testing.
+/// Tests themselves are reported as slightly wrong whatever the policy.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SlightlyWrongPolicy {
+    /// Counts as success with full points.
+    Success,
+    /// Counts as wrong output with no points.
+    Wrong,
+    /// Stays slightly wrong, worth `slightly_wrong_points_fraction` of points.
+    Partial,
+}
+
+impl FromStr for SlightlyWrongPolicy {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<SlightlyWrongPolicy, ()> {
+        match name {
+            "success" => Ok(Self::Success),
+            "wrong" => Ok(Self::Wrong),
+            "partial" => Ok(Self::Partial),
+            _ => Err(()),
+        }
+    }
+}
+
+impl SlightlyWrongPolicy {
+    /// Outcome of a test as counted by the policy.
+    pub fn apply(&self, outcome: TestingOutcome) -> TestingOutcome {
+        match (outcome, self) {
+            (TestingOutcome::SlightlyWrongOutput, Self::Success) => TestingOutcome::Success,
+            (TestingOutcome::SlightlyWrongOutput, Self::Wrong) => TestingOutcome::WrongOutput,
+            _ => outcome,
+        }
+    }
+}
+
+impl TestLog {
+    pub fn outcome(&self) -> TestingOutcome {
+        match self {
+            Self::Success { .. } => TestingOutcome::Success,
+            Self::Timeout { .. } => TestingOutcome::Timeout,
+            Self::MemoryExceeded { .. } => TestingOutcome::MemoryExceeded,
+            Self::OutputLimitExceeded { .. } => TestingOutcome::OutputLimitExceeded,
+            Self::WrongOutput { .. } => TestingOutcome::WrongOutput,
+            Self::SlightlyWrongOutput { .. } => TestingOutcome::SlightlyWrongOutput,
+            Self::NoOutput { .. } => TestingOutcome::NoOutput,
+            Self::NoOutputFile { .. } => TestingOutcome::NoOutputFile,
+            Self::RuntimeError { .. } => TestingOutcome::RuntimeError,
+            Self::Skipped => TestingOutcome::Skipped,
+            Self::InternalError(..) => TestingOutcome::InternalError,
+        }
+    }
+
+    /// Peak memory usage in MiB, for outcomes which measure it.
+    pub fn memory_mib(&self) -> Option<f64> {
+        match self {
+            Self::Success { memory, .. } => Some(*memory),
+            Self::MemoryExceeded { memory_used } => Some(*memory_used),
+            _ => None,
+        }
+    }
+
+    /// Removes outputs of the program and the expected ones from the log,
+    /// together with everything derived from them.
+    pub fn redact(&mut self) {
+        match self {
+            Self::WrongOutput {
+                expected,
+                got,
+                context,
+                expected_token,
+                got_token,
+                checker_message,
+                hint,
+                ..
+            } => {
+                *expected = None;
+                *got = None;
+                *context = None;
+                *expected_token = None;
+                *got_token = None;
+                // Checkers often quote the expected answer.
+                *checker_message = None;
+                *hint = None;
+            }
+            Self::SlightlyWrongOutput { expected, got, .. } => {
+                *expected = None;
+                *got = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sample tests, named `sample_N.in`, are shown to students, others are hidden.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    Sample,
+    Hidden,
+}
+
+/// Sizes in bytes of test's input and program's output, missing when the
+/// test failed before reading the input or the output was not captured.
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct IoSizes {
+    pub input_bytes: Option<u64>,
+    pub output_bytes: Option<u64>,
+    /// Whether the program read all of its stdin, known only in stdin input
+    /// mode. Programs buffering their input may read more than they use.
+    pub input_consumed: Option<bool>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct TestResult {
+    pub test_id: u64,
+    pub visibility: Visibility,
+    pub test_result: TestLog,
+    pub points_awarded: f64,
+    pub max_points: f64,
+    #[serde(flatten)]
+    pub sizes: IoSizes,
+    /// Wall time of the program in milliseconds whatever the outcome,
+    /// missing when the program was not started.
+    pub wall_time_millis: Option<u64>,
+}
+
+impl TestResult {
+    pub fn new(
+        test_id: u64,
+        visibility: Visibility,
+        mut test_result: TestLog,
+        max_points: f64,
+        sizes: IoSizes,
+        wall_time_millis: Option<u64>,
+        config: &JudgeConfig,
+    ) -> TestResult {
+        if visibility == Visibility::Hidden && config.hide_hidden_outputs {
+            test_result.redact();
+        }
+
+        let points_awarded = match config.slightly_wrong_policy.apply(test_result.outcome()) {
+            TestingOutcome::Success => max_points,
+            TestingOutcome::SlightlyWrongOutput => {
+                max_points * config.slightly_wrong_points_fraction
+            }
+            _ => 0.0,
+        };
+
+        TestResult {
+            test_id,
+            visibility,
+            test_result,
+            points_awarded,
+            max_points,
+            sizes,
+            wall_time_millis,
+        }
+    }
+}
+
+/// Number of tests with each outcome, as reported in single tests, so
+/// slightly wrong outputs are counted as such whatever the policy.
+#[derive(Serialize, Default)]
+pub struct TestingSummary {
+    pub tests: usize,
+    pub success: usize,
+    pub timeout: usize,
+    pub memory_exceeded: usize,
+    pub output_limit_exceeded: usize,
+    pub wrong_output: usize,
+    pub slightly_wrong_output: usize,
+    pub no_output: usize,
+    pub no_output_file: usize,
+    pub runtime_error: usize,
+    pub skipped: usize,
+    pub internal_error: usize,
+    pub score: f64,
+    pub max_score: f64,
+    /// Sums over tests where the size is known.
+    pub total_input_bytes: u64,
+    pub total_output_bytes: u64,
+    /// Maxima over tests which measured them, missing when none did. Time
+    /// is the wall time of the program whatever the outcome.
+    pub max_time_millis: Option<u64>,
+    pub max_memory_mib: Option<f64>,
+}
+
+impl TestingSummary {
+    pub fn new(results: &[TestResult], max_score: f64) -> TestingSummary {
+        let mut summary = TestingSummary {
+            tests: results.len(),
+            max_score,
+            ..Default::default()
+        };
+
+        for result in results {
+            summary.score += result.points_awarded;
+            summary.total_input_bytes += result.sizes.input_bytes.unwrap_or_default();
+            summary.total_output_bytes += result.sizes.output_bytes.unwrap_or_default();
+            summary.max_time_millis = summary.max_time_millis.max(result.wall_time_millis);
+            if let Some(memory) = result.test_result.memory_mib() {
+                summary.max_memory_mib =
+                    Some(summary.max_memory_mib.map_or(memory, |max| max.max(memory)));
+            }
+
+            let count = match result.test_result.outcome() {
+                TestingOutcome::Success => &mut summary.success,
+                TestingOutcome::Timeout => &mut summary.timeout,
+                TestingOutcome::MemoryExceeded => &mut summary.memory_exceeded,
+                TestingOutcome::OutputLimitExceeded => &mut summary.output_limit_exceeded,
+                TestingOutcome::WrongOutput => &mut summary.wrong_output,
+                TestingOutcome::SlightlyWrongOutput => &mut summary.slightly_wrong_output,
+                TestingOutcome::NoOutput => &mut summary.no_output,
+                TestingOutcome::NoOutputFile => &mut summary.no_output_file,
+                TestingOutcome::RuntimeError => &mut summary.runtime_error,
+                TestingOutcome::Skipped => &mut summary.skipped,
+                TestingOutcome::InternalError => &mut summary.internal_error,
+            };
+            *count += 1;
+        }
+
+        summary
+    }
+}
+
+/// Failed compilation together with the command that was run, to tell wrong
+/// submissions apart from misconfigured compilers.
+#[derive(Serialize)]
+pub struct CompilationFailure {
+    pub message: String,
+    pub command: Vec<String>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    /// Wall time of the compiler until it failed or was killed.
+    pub compile_time_millis: u64,
+}
+
+#[derive(Serialize)]
+pub enum ProgramResult {
+    CompilationProblem(CompilationFailure),
+    InternalProblem(String),
+    /// Compiler, or interpreter of interpreted languages, is not installed,
+    /// which is a problem of the judge rather than of the submission.
+    CompilerNotFound {
+        compiler: String,
+    },
+    TestingResult {
+        testing_outcome: TestingOutcome,
+        score: f64,
+        max_score: f64,
+        compiler_warnings: Option<String>,
+        /// Wall time of the compiler, missing for interpreted languages.
+        compile_time_millis: Option<u64>,
+        summary: TestingSummary,
+        tests: Vec<TestResult>,
+    },
+    /// Report of a dry run, nothing was compiled or run.
+    ValidationResult {
+        tests: Vec<u64>,
+        max_score: f64,
+        problems: Vec<String>,
+    },
+}
+
+impl ProgramResult {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::CompilationProblem(..) => EXIT_COMPILATION_PROBLEM,
+            Self::InternalProblem(..) | Self::CompilerNotFound { .. } => EXIT_INTERNAL_PROBLEM,
+            Self::ValidationResult { problems, .. } if !problems.is_empty() => {
+                EXIT_INTERNAL_PROBLEM
+            }
+            Self::TestingResult { .. } | Self::ValidationResult { .. } => 0,
+        }
+    }
+}
+
+/// Result as written, with the format version next to its variant.
+#[derive(Serialize)]
+struct VersionedResult<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    result: &'a ProgramResult,
+}
+
+pub fn write_result(output: &OutputConfig, result: &ProgramResult) -> std::io::Result<()> {
+    let versioned = VersionedResult {
+        schema_version: SCHEMA_VERSION,
+        result,
+    };
+    write_json(output, &output.result_name, &versioned)
+}
+
+/// Results of tests finished so far, rewritten after every test.
+#[derive(Serialize)]
+pub struct Progress<'a> {
+    pub tests_total: usize,
+    pub tests: &'a [TestResult],
+}
+
+pub fn write_progress(output: &OutputConfig, progress: &Progress) -> std::io::Result<()> {
+    write_json(output, PROGRESS_NAME, progress)
+}
+
+/// Results of single tests written as newline delimited JSON as they finish,
+/// each line with the outcome of the test next to its result.
+pub struct ResultStream {
+    writer: Box<dyn Write + Send>,
+}
+
+#[derive(Serialize)]
+struct StreamedResult<'a> {
+    outcome: TestingOutcome,
+    #[serde(flatten)]
+    result: &'a TestResult,
+}
+
+impl ResultStream {
+    /// Streams into the file at `path`, or to stdout for `-`.
+    pub fn create(path: &Path) -> std::io::Result<ResultStream> {
+        let writer: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(std::fs::File::create(path)?)
+        };
+
+        Ok(ResultStream { writer })
+    }
+
+    pub fn write(&mut self, result: &TestResult) -> std::io::Result<()> {
+        let line = StreamedResult {
+            outcome: result.test_result.outcome(),
+            result,
+        };
+
+        serde_json::to_writer(&mut self.writer, &line)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+}
+
+/// Value is written to a temporary file first and then renamed, so that
+/// nobody ever reads a partially written one.
+fn write_json(output: &OutputConfig, name: &str, value: &impl Serialize) -> std::io::Result<()> {
+    let serialized = if output.pretty_json {
+        serde_json::to_string_pretty(value)?
+    } else {
+        serde_json::to_string(value)?
+    };
+    let temporary_path = output.dir.join(format!("{name}.tmp"));
+
+    std::fs::write(&temporary_path, serialized)?;
+    std::fs::rename(&temporary_path, output.dir.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(test_result: TestLog, wall_time_millis: Option<u64>) -> TestResult {
+        TestResult {
+            test_id: 1,
+            visibility: Visibility::Hidden,
+            test_result,
+            points_awarded: 0.0,
+            max_points: 1.0,
+            sizes: IoSizes::default(),
+            wall_time_millis,
+        }
+    }
+
+    #[test]
+    fn max_time_covers_every_outcome() {
+        let results = [
+            result(
+                TestLog::Success {
+                    time: 10,
+                    memory: 1.0,
+                },
+                Some(10),
+            ),
+            result(
+                TestLog::RuntimeError {
+                    exit_code: Some(1),
+                    signal: None,
+                    signal_name: None,
+                    process_limit_reached: false,
+                    stderr: String::new(),
+                },
+                Some(900),
+            ),
+            result(TestLog::Skipped, None),
+        ];
+
+        assert_eq!(
+            TestingSummary::new(&results, 3.0).max_time_millis,
+            Some(900)
+        );
+    }
+}
@@ -0,0 +1,473 @@
+//! Restricted profile of the tested program, applied between fork and exec.
+//! Program gets its own network namespace without any interfaces, a mount
+//! namespace where everything except the scratch directory is read-only and
+//! hidden directories are empty, runs as an unprivileged user without any
+//! capabilities and under a seccomp filter allowing only the syscalls on the
+//! list below. Signals can only be sent to its own process group.
+
+use std::ffi::CString;
+use std::fs::Permissions;
+use std::io::{Error, PipeReader, PipeWriter, Read};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Not exported by libc.
+const MOUNT_ATTR_RDONLY: u64 = 0x00000001;
+const AUDIT_ARCH_X86_64: u32 = 0xc000003e;
+const X32_SYSCALL_BIT: u32 = 0x40000000;
+
+/// User and group the program runs as, `nobody` and `nogroup`.
+const SANDBOX_UID: libc::uid_t = 65534;
+const SANDBOX_GID: libc::gid_t = 65534;
+
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_open,
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_stat,
+    libc::SYS_fstat,
+    libc::SYS_fstatfs,
+    libc::SYS_statfs,
+    libc::SYS_lstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_lseek,
+    libc::SYS_access,
+    libc::SYS_faccessat,
+    libc::SYS_faccessat2,
+    libc::SYS_readlink,
+    libc::SYS_readlinkat,
+    libc::SYS_getdents64,
+    libc::SYS_getcwd,
+    libc::SYS_chdir,
+    libc::SYS_fchdir,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_dup,
+    libc::SYS_dup2,
+    libc::SYS_dup3,
+    libc::SYS_pipe,
+    libc::SYS_pipe2,
+    libc::SYS_poll,
+    libc::SYS_ppoll,
+    libc::SYS_select,
+    libc::SYS_pselect6,
+    // Go runtime polls every file it opens.
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_wait,
+    libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2,
+    libc::SYS_ftruncate,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_unlink,
+    libc::SYS_unlinkat,
+    libc::SYS_rename,
+    libc::SYS_mkdir,
+    libc::SYS_mmap,
+    libc::SYS_mprotect,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_madvise,
+    libc::SYS_brk,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_fork,
+    libc::SYS_vfork,
+    libc::SYS_wait4,
+    libc::SYS_waitid,
+    libc::SYS_futex,
+    libc::SYS_set_robust_list,
+    libc::SYS_get_robust_list,
+    libc::SYS_set_tid_address,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_gettimeofday,
+    libc::SYS_time,
+    libc::SYS_times,
+    libc::SYS_getrusage,
+    libc::SYS_getrlimit,
+    libc::SYS_prlimit64,
+    libc::SYS_sysinfo,
+    libc::SYS_uname,
+    libc::SYS_getrandom,
+    libc::SYS_getpid,
+    libc::SYS_getppid,
+    libc::SYS_getpgrp,
+    libc::SYS_gettid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_arch_prctl,
+    // JVM names its threads, the filter itself can not be lifted by it.
+    libc::SYS_prctl,
+    libc::SYS_execve,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_restart_syscall,
+];
+
+/// Written by the child when applying the profile fails.
+const FAILED: &[u8] = b"1";
+
+/// Failures of applying the profile are reported by `spawn` the same way as
+/// failures to execute the program. The child tells them apart by writing to
+/// a pipe which exec would close.
+pub struct Sandbox {
+    failures: PipeReader,
+    _failures_writer: PipeWriter,
+}
+
+impl Sandbox {
+    /// Whether starting the program failed because of the profile.
+    pub fn failed(&self) -> bool {
+        let mut written = [0; FAILED.len()];
+        (&self.failures)
+            .read(&mut written)
+            .is_ok_and(|read| read > 0)
+    }
+}
+
+/// Makes the command run under the restricted profile, with `scratch_dir`
+/// being the only writable place and `hidden_dirs` replaced by empty ones.
+/// Requires root privileges of the judge. Returned sandbox has to be kept
+/// until the command is spawned.
+pub fn restrict(
+    command: &mut Command,
+    scratch_dir: &Path,
+    hidden_dirs: &[PathBuf],
+) -> Result<Sandbox, String> {
+    let mut filter = syscall_filter()?;
+    std::os::unix::fs::chown(scratch_dir, Some(SANDBOX_UID), Some(SANDBOX_GID))
+        .map_err(|error| format!("Unable to hand over scratch directory: {error}"))?;
+    let scratch_dir = c_path(scratch_dir)?;
+    let hidden_dirs = hidden_dirs
+        .iter()
+        .filter(|dir| dir.is_dir())
+        .map(|dir| c_path(dir))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (failures, failures_writer) = std::io::pipe().map_err(|error| error.to_string())?;
+    // Nothing is written when the profile was applied.
+    check(unsafe { libc::fcntl(failures.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) }.into())
+        .map_err(|error| error.to_string())?;
+    let failures_fd = failures_writer.as_raw_fd();
+
+    // Everything is allocated up front, nothing allocates after fork.
+    unsafe {
+        command.pre_exec(move || {
+            let applied = isolate(&scratch_dir, &hidden_dirs)
+                .and_then(|()| drop_privileges())
+                .and_then(|()| {
+                    filter.confine_signals(libc::getpgrp());
+                    install_filter(&filter.program)
+                });
+            if applied.is_err() {
+                libc::write(
+                    failures_fd,
+                    FAILED.as_ptr() as *const libc::c_void,
+                    FAILED.len(),
+                );
+            }
+            applied
+        });
+    }
+
+    Ok(Sandbox {
+        failures,
+        _failures_writer: failures_writer,
+    })
+}
+
+/// Makes the program directory readable by the unprivileged user, files
+/// executable by their owner become executable by everyone.
+pub fn expose(program_dir: &Path) -> std::io::Result<()> {
+    std::fs::set_permissions(program_dir, Permissions::from_mode(0o755))?;
+
+    for entry in std::fs::read_dir(program_dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        // Permissions of a link are those of its target, which may be anywhere.
+        if file_type.is_symlink() {
+            continue;
+        }
+        if file_type.is_dir() {
+            expose(&entry.path())?;
+            continue;
+        }
+
+        let mode = entry.metadata()?.permissions().mode();
+        let executable = if mode & 0o100 != 0 { 0o111 } else { 0 };
+        std::fs::set_permissions(
+            entry.path(),
+            Permissions::from_mode(mode | 0o444 | executable),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn c_path(path: &Path) -> Result<CString, String> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| format!("Path {} contains a nul byte.", path.display()))
+}
+
+fn check(result: libc::c_long) -> std::io::Result<()> {
+    if result == -1 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn isolate(scratch_dir: &CString, hidden_dirs: &[CString]) -> std::io::Result<()> {
+    let root = c"/";
+
+    unsafe {
+        check(libc::unshare(libc::CLONE_NEWNET | libc::CLONE_NEWNS).into())?;
+        // Changes below must not propagate back to the judge's namespace.
+        check(
+            libc::mount(
+                std::ptr::null(),
+                root.as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            )
+            .into(),
+        )?;
+        for hidden_dir in hidden_dirs {
+            check(
+                libc::mount(
+                    c"tmpfs".as_ptr(),
+                    hidden_dir.as_ptr(),
+                    c"tmpfs".as_ptr(),
+                    libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                    std::ptr::null(),
+                )
+                .into(),
+            )?;
+        }
+        check(
+            libc::mount(
+                scratch_dir.as_ptr(),
+                scratch_dir.as_ptr(),
+                std::ptr::null(),
+                libc::MS_BIND | libc::MS_REC,
+                std::ptr::null(),
+            )
+            .into(),
+        )?;
+        set_mount_attributes(root, MOUNT_ATTR_RDONLY, 0)?;
+        set_mount_attributes(scratch_dir, 0, MOUNT_ATTR_RDONLY)?;
+        // Program starts in the scratch directory, which was entered before
+        // the writable mount covered it.
+        check(libc::chdir(scratch_dir.as_ptr()).into())?;
+    }
+
+    Ok(())
+}
+
+/// Switches to the unprivileged user for good. Capabilities are cleared by
+/// the switch itself, those of the bounding set could only be regained by
+/// executing privileged programs, which no new privileges forbids anyway.
+fn drop_privileges() -> std::io::Result<()> {
+    unsafe {
+        // Fails past the last capability the kernel knows.
+        for capability in 0.. {
+            if libc::prctl(libc::PR_CAPBSET_DROP, capability, 0, 0, 0) == -1 {
+                break;
+            }
+        }
+        check(
+            libc::prctl(
+                libc::PR_CAP_AMBIENT,
+                libc::PR_CAP_AMBIENT_CLEAR_ALL,
+                0,
+                0,
+                0,
+            )
+            .into(),
+        )?;
+        check(libc::setgroups(0, std::ptr::null()).into())?;
+        check(libc::setresgid(SANDBOX_GID, SANDBOX_GID, SANDBOX_GID).into())?;
+        check(libc::setresuid(SANDBOX_UID, SANDBOX_UID, SANDBOX_UID).into())
+    }
+}
+
+fn set_mount_attributes(path: &std::ffi::CStr, set: u64, clear: u64) -> std::io::Result<()> {
+    let attributes = MountAttr {
+        attr_set: set,
+        attr_clr: clear,
+        propagation: 0,
+        userns_fd: 0,
+    };
+
+    check(unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            libc::AT_RECURSIVE,
+            &attributes as *const MountAttr,
+            std::mem::size_of::<MountAttr>(),
+        )
+    })
+}
+
+/// Seccomp program, the process group of the program is only known after fork.
+struct Filter {
+    program: Vec<libc::sock_filter>,
+    /// Instructions comparing the signalled process with the process group.
+    group: usize,
+    negated_group: usize,
+}
+
+impl Filter {
+    fn confine_signals(&mut self, group: libc::pid_t) {
+        self.program[self.group].k = group as u32;
+        self.program[self.negated_group].k = group.wrapping_neg() as u32;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn syscall_filter() -> Result<Filter, String> {
+    let statement = |code: u32, k: u32| unsafe { libc::BPF_STMT(code as u16, k) };
+    let jump =
+        |code: u32, k: u32, jt: u8, jf: u8| unsafe { libc::BPF_JUMP(code as u16, k, jt, jf) };
+    let errno = libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA);
+    // Kernel only looks at the lower half of pid arguments.
+    let first_argument = std::mem::offset_of!(libc::seccomp_data, args) as u32;
+
+    let mut filter = vec![
+        // Syscall numbers are only meaningful for the architecture they come from.
+        statement(
+            libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+            std::mem::offset_of!(libc::seccomp_data, arch) as u32,
+        ),
+        jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            AUDIT_ARCH_X86_64,
+            1,
+            0,
+        ),
+        statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        statement(
+            libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+            std::mem::offset_of!(libc::seccomp_data, nr) as u32,
+        ),
+        jump(
+            libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+            X32_SYSCALL_BIT,
+            0,
+            1,
+        ),
+        statement(libc::BPF_RET | libc::BPF_K, errno),
+    ];
+
+    // `kill` may only target the whole group, `tgkill` only the process
+    // started by the judge, which is the leader of the group.
+    filter.extend([
+        jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            libc::SYS_kill as u32,
+            0,
+            5,
+        ),
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, first_argument),
+        jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, 0, 2, 0),
+    ]);
+    let negated_group = filter.len();
+    filter.extend([
+        jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, 0, 1, 0),
+        statement(libc::BPF_RET | libc::BPF_K, errno),
+        statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW),
+        jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            libc::SYS_tgkill as u32,
+            0,
+            4,
+        ),
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, first_argument),
+    ]);
+    let group = filter.len();
+    filter.extend([
+        jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, 0, 1, 0),
+        statement(libc::BPF_RET | libc::BPF_K, errno),
+        statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW),
+    ]);
+
+    for syscall in ALLOWED_SYSCALLS {
+        filter.push(jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            *syscall as u32,
+            0,
+            1,
+        ));
+        filter.push(statement(
+            libc::BPF_RET | libc::BPF_K,
+            libc::SECCOMP_RET_ALLOW,
+        ));
+    }
+    filter.push(statement(libc::BPF_RET | libc::BPF_K, errno));
+
+    Ok(Filter {
+        program: filter,
+        group,
+        negated_group,
+    })
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn syscall_filter() -> Result<Filter, String> {
+    Err("Sandbox is only supported on x86_64.".into())
+}
+
+fn install_filter(filter: &[libc::sock_filter]) -> std::io::Result<()> {
+    let program = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut libc::sock_filter,
+    };
+
+    unsafe {
+        // Filter can only be installed without CAP_SYS_ADMIN after giving up privileges.
+        check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0).into())?;
+        check(
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            )
+            .into(),
+        )
+    }
+}
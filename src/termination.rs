@@ -0,0 +1,31 @@
+//! Termination requested by SIGTERM or SIGINT. Instead of dying right away,
+//! the judge stops testing, kills the running programs and reports the
+//! tests finished so far.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Replaces the default handlers of SIGTERM and SIGINT.
+pub fn install() -> std::io::Result<()> {
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = request as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+
+        if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Only async-signal-safe work may be done here.
+extern "C" fn request(_signal: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}